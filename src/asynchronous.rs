@@ -0,0 +1,277 @@
+//! Asynchronous variants of the executor's methods, built on tokio.
+
+use std::ffi::OsStr;
+use std::future::Future;
+use std::io::{self, Write};
+use std::panic::Location;
+use std::time::Instant;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+use crate::spawn::{self, Captured, Drain, Drained, Waited};
+use crate::{Execution, ExecutionError, Executor, RunOutcome};
+
+impl<K, V> Executor<K, V>
+where
+    K: AsRef<OsStr> + Clone,
+    V: AsRef<OsStr> + Clone,
+{
+    /// Launches a new subprocess and awaits its completion asynchronously,
+    /// returning the captured output.
+    ///
+    /// Identical to [`Executor::run`], other than not blocking the
+    /// calling thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`Executor::run`]. The panic
+    /// is raised where the future is polled, so the message names the
+    /// location of the caller instead.
+    #[track_caller]
+    pub fn run_async<I, S>(&self, args: I) -> impl Future<Output = RunOutcome> + '_
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let caller = Location::caller();
+        let exec = self.prepare(args);
+        async move {
+            let result = match exec {
+                Ok(exec) => self.execute_async(exec).await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(output) => output,
+                Err(err) => {
+                    let msg = self.render(&err);
+                    let separator = if msg.ends_with('\n') { "" } else { "\n" };
+                    self.fail_with(
+                        err,
+                        format_args!("{}{}Called at {}", msg, separator, caller),
+                    )
+                }
+            }
+        }
+    }
+
+    /// Launches a new subprocess and awaits its completion asynchronously,
+    /// returning the captured output on success.
+    ///
+    /// Identical to [`Executor::try_run`], other than not blocking the
+    /// calling thread.
+    pub async fn try_run_async<I, S>(&self, args: I) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.execute_async(self.prepare(args)?).await
+    }
+
+    /// Launches the prepared execution, retrying it as configured, and
+    /// awaits its completion.
+    async fn execute_async(&self, mut exec: Execution) -> Result<RunOutcome, ExecutionError> {
+        if self.dry_run {
+            exec.print(self);
+            return exec.into_success();
+        }
+        loop {
+            #[cfg(feature = "tracing")]
+            let span = crate::trace::span(&exec, self.context.as_deref());
+            let attempt = self.attempt_async(&mut exec);
+            #[cfg(feature = "tracing")]
+            let attempt = tracing::Instrument::instrument(attempt, span.clone());
+            attempt.await?;
+            #[cfg(feature = "tracing")]
+            crate::trace::completed(&span, &exec);
+            if exec.succeeded() || exec.attempts > self.retries {
+                return exec.into_success();
+            }
+            tokio::time::sleep(self.retry_delay).await;
+        }
+    }
+
+    /// Launches the command once, and awaits its completion.
+    async fn attempt_async(&self, exec: &mut Execution) -> Result<(), ExecutionError> {
+        self.before_launch(exec);
+        let started = Instant::now();
+        let waited = self.output_async(self.command(exec)).await;
+        exec.elapsed = Some(started.elapsed());
+        exec.complete(self, waited)?;
+        Ok(())
+    }
+
+    /// The asynchronous equivalent of [`spawn::output`](crate::spawn::output).
+    async fn output_async(&self, mut command: std::process::Command) -> io::Result<Waited> {
+        let config = self.spawn_config();
+        command.stdin(config.stdin()?);
+        let merged = config.connect_output(&mut command)?;
+        let mut command = Command::from(command);
+        let mut child = command.kill_on_drop(true).spawn()?;
+        let pid = child.id();
+        // Releases the command's copies of any merged pipe, so that EOF
+        // is observed once the child exits.
+        drop(command);
+        // The merged pipe cannot be read asynchronously, so it is read
+        // on a background thread.
+        let merged = merged.map(|pipe| spawn::drain_stdout(pipe, &config));
+        let mut stdin = child.stdin.take();
+        let stdout_pipe = child.stdout.take();
+        let stderr_pipe = child.stderr.take();
+
+        let limit = self.max_output_bytes;
+        let mut stdout = Drained::default();
+        let mut stderr = Drained::default();
+        let completion = async {
+            let write = async {
+                if let (Some(pipe), Some(data)) = (stdin.as_mut(), self.stdin.as_deref()) {
+                    // As in the synchronous case, the child may exit
+                    // without consuming all of stdin.
+                    let _ = pipe.write_all(data).await;
+                }
+                // Closes the pipe, signalling EOF.
+                drop(stdin.take());
+            };
+            let merged_closed = async {
+                if let Some(drain) = &merged {
+                    let _ = tokio::task::spawn_blocking(drain.closed()).await;
+                }
+            };
+            let (status, _, _, _, _) = tokio::join!(
+                child.wait(),
+                write,
+                merged_closed,
+                read_to_end(stdout_pipe, &mut stdout, limit, config.stdout_tee()),
+                read_to_end(stderr_pipe, &mut stderr, limit, config.stderr_tee()),
+            );
+            status
+        };
+        let completed = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, completion).await.ok(),
+            None => Some(completion.await),
+        };
+        match completed {
+            Some(status) => {
+                let stdout = merged.map(Drain::finish).unwrap_or(stdout);
+                Ok(Waited::Completed(Captured::new(
+                    pid, status?, stdout, stderr,
+                )))
+            }
+            None => {
+                #[cfg(unix)]
+                let signalled = match (config.timeout_kill, child.id()) {
+                    (Some((signal, grace)), Some(pid)) if spawn::signal_child(pid, signal) => {
+                        tokio::time::timeout(grace, child.wait()).await.ok()
+                    }
+                    _ => None,
+                };
+                #[cfg(not(unix))]
+                let signalled = None;
+                let status = match signalled {
+                    Some(status) => status?,
+                    None => {
+                        // The child may have exited since it was polled;
+                        // either way, it is reaped below.
+                        let _ = child.start_kill();
+                        child.wait().await?
+                    }
+                };
+                let stdout = merged.map(Drain::finish_within).unwrap_or(stdout);
+                Ok(Waited::TimedOut(Captured::new(pid, status, stdout, stderr)))
+            }
+        }
+    }
+}
+
+/// Reads `pipe` to completion, if it exists, echoing each chunk to `tee`
+/// as it is read. Only up to `limit` bytes are retained.
+async fn read_to_end<R: AsyncRead + Unpin, W: Write>(
+    pipe: Option<R>,
+    buf: &mut Drained,
+    limit: Option<usize>,
+    mut tee: Option<W>,
+) {
+    let mut pipe = match pipe {
+        Some(pipe) => pipe,
+        None => return,
+    };
+    let mut chunk = [0; 4096];
+    loop {
+        let n = match pipe.read(&mut chunk).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        buf.extend(&chunk[..n], limit);
+        if let Some(tee) = tee.as_mut() {
+            let _ = tee.write_all(&chunk[..n]).and_then(|_| tee.flush());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn run_async_exports_environment_variable() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stdin("BAZ");
+        let output = executor
+            .run_async(vec!["/bin/bash", "-c", "echo $FOO; cat"])
+            .await;
+        assert_eq!(output.stdout, b"BAR\nBAZ");
+    }
+
+    #[tokio::test]
+    async fn run_async_with_large_stdin_and_output() {
+        let input: Vec<u8> = (0..8 << 20).map(|i| (i % 251) as u8).collect();
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_stdin(input.clone())
+            .with_timeout(Duration::from_secs(60));
+        let output = executor.run_async(vec!["/bin/cat"]).await;
+        assert!(output.stdout == input);
+    }
+
+    #[tokio::test]
+    async fn run_async_with_merged_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_merged_output();
+        let output = executor
+            .run_async(vec!["/bin/bash", "-c", "echo $FOO; echo oops >&2"])
+            .await;
+        assert_eq!(output.stdout, b"BAR\noops\n");
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "exit status: 1")]
+    async fn run_async_failure() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_async(vec!["/bin/false"]).await;
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Called at src/asynchronous.rs:")]
+    async fn run_async_failure_names_caller() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_async(vec!["/bin/false"]).await;
+    }
+
+    #[tokio::test]
+    async fn run_async_counts_stats() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stats();
+        executor.run_async(vec!["/bin/true"]).await;
+        assert!(executor.try_run_async(vec!["/bin/false"]).await.is_err());
+        let stats = executor.stats();
+        assert_eq!((stats.launches, stats.failures), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn try_run_async_reports_timeout() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_timeout(Duration::from_millis(100));
+        assert!(matches!(
+            executor.try_run_async(vec!["/bin/sleep", "10"]).await,
+            Err(ExecutionError::TimedOut { .. })
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+//! Collapsible groups of output in the logs of CI systems.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The CI system for whose logs output is grouped, as configured by
+/// [`Executor::with_ci_grouping`](crate::Executor::with_ci_grouping).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CiFlavor {
+    /// Detect the CI system from the environment of the caller, grouping
+    /// nothing if it is not recognized.
+    Auto,
+    /// GitHub Actions, with `::group::` and `::endgroup::` commands.
+    GitHub,
+    /// GitLab CI, with `section_start` and `section_end` markers.
+    GitLab,
+}
+
+impl CiFlavor {
+    /// Returns the CI system in use, if recognized.
+    fn detect(self) -> Option<CiFlavor> {
+        match self {
+            CiFlavor::Auto => {
+                if std::env::var_os("GITHUB_ACTIONS").is_some_and(|v| v == "true") {
+                    Some(CiFlavor::GitHub)
+                } else if std::env::var_os("GITLAB_CI").is_some() {
+                    Some(CiFlavor::GitLab)
+                } else {
+                    None
+                }
+            }
+            flavor => Some(flavor),
+        }
+    }
+}
+
+/// Distinguishes the sections of GitLab CI, which must be uniquely named.
+static SECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// A group of output, which is closed when dropped.
+///
+/// Markers are written to stderr directly, rather than with `eprintln!`,
+/// so that they are ordered with the output of sub-processes writing to
+/// the same descriptor.
+pub(crate) struct Group {
+    flavor: CiFlavor,
+    section: usize,
+}
+
+impl Group {
+    /// Opens a group titled `title`, if `flavor` is in use.
+    pub(crate) fn start(flavor: CiFlavor, title: &str) -> Option<Group> {
+        let flavor = flavor.detect()?;
+        let section = SECTIONS.fetch_add(1, Ordering::Relaxed);
+        let marker = match flavor {
+            CiFlavor::GitLab => format!(
+                "\x1b[0Ksection_start:{}:command_{}[collapsed=true]\r\x1b[0K{}\n",
+                timestamp(),
+                section,
+                title
+            ),
+            _ => format!("::group::{}\n", title),
+        };
+        let _ = io::stderr().write_all(marker.as_bytes());
+        Some(Group { flavor, section })
+    }
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        let marker = match self.flavor {
+            CiFlavor::GitLab => format!(
+                "\x1b[0Ksection_end:{}:command_{}\r\x1b[0K\n",
+                timestamp(),
+                self.section
+            ),
+            _ => "::endgroup::\n".to_string(),
+        };
+        let _ = io::stderr().write_all(marker.as_bytes());
+    }
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+use std::io::IsTerminal;
+
+/// Controls whether diagnostics are decorated with ANSI color codes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Always emit color codes.
+    Always,
+    /// Never emit color codes.
+    Never,
+    /// Emit color codes only if stderr, where panic messages are
+    /// printed, is a terminal.
+    #[default]
+    Auto,
+}
+
+impl ColorChoice {
+    /// Returns true if color codes should be emitted.
+    pub(crate) fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => std::io::stderr().is_terminal(),
+        }
+    }
+}
+
+pub(crate) const COMMAND: &str = "\x1b[95m";
+pub(crate) const STDOUT: &str = "\x1b[92m";
+pub(crate) const STDERR: &str = "\x1b[91m";
+const RESET: &str = "\x1b[0m";
+
+/// Returns `text`, wrapped in the color code `code` if `color` is set.
+pub(crate) fn painted(color: bool, code: &str, text: &str) -> String {
+    if color {
+        format!("{}{}{}", code, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Writes `text`, wrapped in the color code `code` if `color` is set.
+pub(crate) fn paint<T: fmt::Display>(
+    f: &mut fmt::Formatter,
+    color: bool,
+    code: &str,
+    text: T,
+) -> fmt::Result {
+    if color {
+        write!(f, "{}{}{}", code, text, RESET)
+    } else {
+        write!(f, "{}", text)
+    }
+}
@@ -0,0 +1,94 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::process::Output;
+use std::time::Duration;
+
+use crate::{Execution, ExecutionError, RunOutcome};
+
+/// A command which was launched and awaited by
+/// [`Executor::execute`](crate::Executor::execute), whether or not it
+/// succeeded.
+///
+/// Provides the data from which failure diagnostics are formatted. The
+/// `Display` impl formats the execution exactly as it appears in those
+/// diagnostics, for embedding in other messages.
+pub struct CompletedExecution {
+    exec: Execution,
+}
+
+impl CompletedExecution {
+    pub(crate) fn new(exec: Execution) -> Self {
+        CompletedExecution { exec }
+    }
+
+    /// Returns the program which was launched.
+    pub fn program(&self) -> &OsStr {
+        &self.exec.cmd
+    }
+
+    /// Returns the arguments provided to the program, after any
+    /// interpolation of variables.
+    pub fn args(&self) -> &[OsString] {
+        &self.exec.args
+    }
+
+    /// Returns the exit status and captured output of the most recent
+    /// launch.
+    pub fn output(&self) -> &Output {
+        self.exec.output()
+    }
+
+    /// Returns how long the most recent launch took, or `None` if the
+    /// command was not launched (such as in a dry run).
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.exec.elapsed
+    }
+
+    /// Returns the number of times the command was launched, which
+    /// exceeds one if it was retried.
+    pub fn attempts(&self) -> usize {
+        self.exec.attempts
+    }
+
+    /// Returns the timeout which the command exceeded, if it was killed
+    /// for doing so.
+    pub fn timed_out(&self) -> Option<Duration> {
+        self.exec.timed_out
+    }
+
+    /// Returns true if the command succeeded, under the conditions which
+    /// [`Executor::run`](crate::Executor::run) checks.
+    pub fn succeeded(&self) -> bool {
+        self.exec.succeeded()
+    }
+
+    /// Converts the execution into its output, regardless of whether it
+    /// succeeded.
+    pub fn into_outcome(self) -> RunOutcome {
+        self.exec.into_outcome()
+    }
+
+    /// Converts the execution into its output if it succeeded, or the
+    /// error which [`Executor::try_run`](crate::Executor::try_run) would
+    /// have returned otherwise.
+    pub fn into_result(self) -> Result<RunOutcome, ExecutionError> {
+        self.exec.into_success()
+    }
+}
+
+impl fmt::Display for CompletedExecution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.exec.fmt(f)
+    }
+}
+
+impl fmt::Debug for CompletedExecution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CompletedExecution")
+            .field("program", &self.program())
+            .field("args", &self.args())
+            .field("output", self.output())
+            .field("timed_out", &self.timed_out())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,131 @@
+//! Line-based diffs of expected and actual output.
+
+/// The number of unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+/// A line of a diff.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Formats the differences between `expected` and `actual` as a unified
+/// diff, with the given names for each side.
+///
+/// Lines are compared without their line endings. Returns an empty
+/// string if the lines are identical.
+pub(crate) fn unified(expected: &str, actual: &str, from: &str, to: &str) -> String {
+    let lines = diff(
+        &expected.lines().collect::<Vec<_>>(),
+        &actual.lines().collect::<Vec<_>>(),
+    );
+    if lines.iter().all(|line| matches!(line, Line::Same(_))) {
+        return String::new();
+    }
+    let mut out = format!("--- {}\n+++ {}\n", from, to);
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|&i| !matches!(lines[i], Line::Same(_)))
+        .collect();
+    // Groups changes which are close enough to share their context.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.1 => hunk.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    for (start, end) in hunks {
+        // The line numbers at which the hunk starts on each side.
+        let (mut old, mut new) = (1, 1);
+        for line in &lines[..start] {
+            match line {
+                Line::Same(_) => (old, new) = (old + 1, new + 1),
+                Line::Removed(_) => old += 1,
+                Line::Added(_) => new += 1,
+            }
+        }
+        let hunk = &lines[start..end];
+        let old_len = hunk
+            .iter()
+            .filter(|line| !matches!(line, Line::Added(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|line| !matches!(line, Line::Removed(_)))
+            .count();
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old, old_len, new, new_len
+        ));
+        for line in hunk {
+            let (prefix, text) = match line {
+                Line::Same(text) => (' ', text),
+                Line::Removed(text) => ('-', text),
+                Line::Added(text) => ('+', text),
+            };
+            out.push(prefix);
+            out.push_str(text);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Computes a minimal sequence of edits from `old` to `new`, from their
+/// longest common subsequence.
+fn diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    // `common[i][j]` is the length of the longest common subsequence of
+    // `old[i..]` and `new[j..]`.
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(Line::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(Line::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unified_diff_of_changed_lines() {
+        let expected = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        let actual = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        assert_eq!(
+            unified(expected, actual, "golden", "stdout"),
+            "--- golden\n+++ stdout\n\
+             @@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n\
+             @@ -7,3 +7,4 @@\n g\n h\n i\n+j\n"
+        );
+    }
+
+    #[test]
+    fn unified_diff_of_identical_lines() {
+        assert_eq!(unified("a\nb", "a\nb\n", "golden", "stdout"), "");
+    }
+}
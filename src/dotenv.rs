@@ -0,0 +1,147 @@
+//! Parsing of dotenv (`.env`) files.
+
+use std::fmt;
+
+/// A line of a dotenv file which could not be parsed.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ParseError {
+    /// The (one-based) number of the line.
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// Parses the variables defined by the contents of a dotenv file, in
+/// order.
+///
+/// Each line is either blank, a comment beginning with `#`, or an
+/// assignment `KEY=VALUE`, optionally prefixed by `export`. Values may
+/// be:
+///
+/// - Unquoted, extending to the end of the line (or a ` #` comment),
+///   without surrounding whitespace.
+/// - Single-quoted, preserving everything until the closing quote.
+/// - Double-quoted, preserving everything until the closing quote, other
+///   than the escapes `\n`, `\"` and `\\`.
+///
+/// Quoted values may not span multiple lines.
+pub(crate) fn parse(contents: &str) -> Result<Vec<(String, String)>, ParseError> {
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            Some(parse_line(line).map_err(|reason| ParseError {
+                line: index + 1,
+                reason,
+            }))
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Result<(String, String), &'static str> {
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let (key, value) = line.split_once('=').ok_or("expected KEY=VALUE")?;
+    let key = key.trim();
+    let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+    if !valid
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    {
+        return Err("invalid variable name");
+    }
+    let value = value.trim_start();
+    let (value, rest) = match value.chars().next() {
+        Some('\'') => {
+            let end = value[1..].find('\'').ok_or("unterminated single quote")?;
+            (value[1..end + 1].to_string(), &value[end + 2..])
+        }
+        Some('"') => {
+            let mut unescaped = String::new();
+            let mut chars = value[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => unescaped.push('\n'),
+                        Some((_, c @ '"')) | Some((_, c @ '\\')) => unescaped.push(c),
+                        Some((_, c)) => {
+                            unescaped.push('\\');
+                            unescaped.push(c);
+                        }
+                        None => return Err("unterminated double quote"),
+                    },
+                    Some((_, c)) => unescaped.push(c),
+                    None => return Err("unterminated double quote"),
+                }
+            };
+            (unescaped, &value[end..])
+        }
+        _ => {
+            let value = match value.find(" #") {
+                Some(comment) => &value[..comment],
+                None => value,
+            };
+            (value.trim_end().to_string(), "")
+        }
+    };
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err("unexpected characters after quoted value");
+    }
+    Ok((key.to_string(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_assignments() {
+        let contents = r#"
+# A comment
+FOO=bar
+export BAZ = qux quux # trailing comment
+EMPTY=
+SINGLE='$HOME # not a comment'
+DOUBLE="a \"b\"\nc" # comment
+URL=http://host/#anchor
+"#;
+        assert_eq!(
+            parse(contents).unwrap(),
+            vec![
+                ("FOO".to_string(), "bar".to_string()),
+                ("BAZ".to_string(), "qux quux".to_string()),
+                ("EMPTY".to_string(), "".to_string()),
+                ("SINGLE".to_string(), "$HOME # not a comment".to_string()),
+                ("DOUBLE".to_string(), "a \"b\"\nc".to_string()),
+                ("URL".to_string(), "http://host/#anchor".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_malformed_lines() {
+        let error = |line, reason| Err(ParseError { line, reason });
+        assert_eq!(parse("FOO=bar\nBAZ"), error(2, "expected KEY=VALUE"));
+        assert_eq!(parse("1FOO=bar"), error(1, "invalid variable name"));
+        assert_eq!(parse("\n\nFOO='bar"), error(3, "unterminated single quote"));
+        assert_eq!(
+            parse("FOO=\"bar\\\""),
+            error(1, "unterminated double quote")
+        );
+        assert_eq!(
+            parse("FOO='bar' baz"),
+            error(1, "unexpected characters after quoted value")
+        );
+    }
+}
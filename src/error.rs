@@ -0,0 +1,287 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{fmt_execution, ColorChoice, Stream, StreamContents};
+
+/// The number of lines of each stream included in the message of an
+/// error converted by [`ExecutionError::into_io_error`].
+const IO_ERROR_TAIL: usize = 20;
+
+/// A failure to launch a sub-process, or to have it complete successfully.
+///
+/// As a `std::error::Error`, this converts into an `anyhow::Error` with
+/// `?`. It also converts into an `io::Error`, as with
+/// [`ExecutionError::into_io_error`].
+#[derive(Debug)]
+pub enum ExecutionError {
+    /// No command was supplied, as the arguments were empty.
+    EmptyArgs,
+    /// The sub-process could not be executed.
+    SpawnFailed {
+        /// The program which was launched.
+        program: OsString,
+        source: io::Error,
+    },
+    /// The sub-process ran, but did not exit successfully (a non-zero
+    /// code, or termination by a signal).
+    NonZeroExit {
+        command: Vec<OsString>,
+        status: ExitStatus,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    },
+    /// The sub-process exceeded its timeout, and was killed.
+    ///
+    /// Contains any output captured before the sub-process was killed.
+    TimedOut {
+        command: Vec<OsString>,
+        timeout: Duration,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    },
+    /// The sub-process completed, but did not meet the expectation of an
+    /// assertion helper, as described by `reason`.
+    Unexpected {
+        reason: String,
+        command: Vec<OsString>,
+        status: ExitStatus,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    },
+    /// An argument referenced an undefined environment variable, with
+    /// [`Executor::with_arg_interpolation`](crate::Executor::with_arg_interpolation).
+    UndefinedVariable { name: String, arg: String },
+    /// A failure of a method which checks more than a single command, or
+    /// more than its termination, as reported to a
+    /// [`FailureStrategy::Custom`] handler, and described by `message`.
+    Failed { message: String },
+    /// The sub-process wrote invalid UTF-8 to `stream`, where it was
+    /// expected to write text.
+    InvalidUtf8 {
+        stream: Stream,
+        /// The length of the valid UTF-8 prefix of `raw`.
+        valid_up_to: usize,
+        /// The bytes written to the stream.
+        raw: Vec<u8>,
+    },
+}
+
+impl ExecutionError {
+    /// An error for the invalid UTF-8 written to `stream`.
+    pub(crate) fn invalid_utf8(stream: Stream, raw: Vec<u8>) -> Self {
+        let valid_up_to = match std::str::from_utf8(&raw) {
+            Ok(valid) => valid.len(),
+            Err(err) => err.valid_up_to(),
+        };
+        ExecutionError::InvalidUtf8 {
+            stream,
+            valid_up_to,
+            raw,
+        }
+    }
+
+    /// Converts the error into an `io::Error`, for propagation from
+    /// functions returning `io::Result`.
+    ///
+    /// The message describes the command line and how it terminated,
+    /// without colors, and only the last 20 lines of each stream. The kind
+    /// is that of the underlying error for failures to spawn,
+    /// `TimedOut` for timeouts, and `Other` otherwise.
+    pub fn into_io_error(self) -> io::Error {
+        let kind = match &self {
+            ExecutionError::SpawnFailed { source, .. } => source.kind(),
+            ExecutionError::TimedOut { .. } => io::ErrorKind::TimedOut,
+            _ => io::ErrorKind::Other,
+        };
+        let styled = Styled {
+            err: &self,
+            color: false,
+            tail: Some(IO_ERROR_TAIL),
+        };
+        io::Error::new(kind, styled.to_string())
+    }
+
+    /// Displays the error, decorated with color codes if `color` is set.
+    pub(crate) fn styled(&self, color: bool) -> Styled<'_> {
+        Styled {
+            err: self,
+            color,
+            tail: None,
+        }
+    }
+}
+
+impl From<ExecutionError> for io::Error {
+    fn from(err: ExecutionError) -> Self {
+        err.into_io_error()
+    }
+}
+
+/// Displays colors according to [`ColorChoice::Auto`](crate::ColorChoice::Auto).
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.styled(ColorChoice::Auto.enabled()).fmt(f)
+    }
+}
+
+pub(crate) struct Styled<'a> {
+    err: &'a ExecutionError,
+    color: bool,
+    /// The number of lines of each stream shown, if limited.
+    tail: Option<usize>,
+}
+
+impl fmt::Display for Styled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let color = self.color;
+        match self.err {
+            ExecutionError::EmptyArgs => write!(f, "Missing command: no arguments were provided"),
+            ExecutionError::SpawnFailed { program, source } => write!(
+                f,
+                "Failed to spawn `{}`: {}",
+                program.to_string_lossy(),
+                source
+            ),
+            ExecutionError::NonZeroExit {
+                command,
+                status,
+                stdout,
+                stderr,
+            } => fmt_execution(
+                f,
+                color,
+                command,
+                Some(status),
+                StreamContents::Captured(stdout),
+                StreamContents::Captured(stderr),
+                self.tail,
+            ),
+            ExecutionError::TimedOut {
+                command,
+                timeout,
+                stdout,
+                stderr,
+            } => {
+                writeln!(f, "Timed out after {:?}", timeout)?;
+                fmt_execution(
+                    f,
+                    color,
+                    command,
+                    None,
+                    StreamContents::Captured(stdout),
+                    StreamContents::Captured(stderr),
+                    self.tail,
+                )
+            }
+            ExecutionError::Unexpected {
+                reason,
+                command,
+                status,
+                stdout,
+                stderr,
+            } => {
+                writeln!(f, "{}", reason)?;
+                fmt_execution(
+                    f,
+                    color,
+                    command,
+                    Some(status),
+                    StreamContents::Captured(stdout),
+                    StreamContents::Captured(stderr),
+                    self.tail,
+                )
+            }
+            ExecutionError::UndefinedVariable { name, arg } => {
+                write!(f, "Undefined variable `{}` in argument {:?}", name, arg)
+            }
+            ExecutionError::Failed { message } => write!(f, "{}", message),
+            ExecutionError::InvalidUtf8 {
+                stream,
+                valid_up_to,
+                ..
+            } => write!(
+                f,
+                "Command wrote invalid UTF-8 to {}, after {} valid bytes",
+                stream, valid_up_to
+            ),
+        }
+    }
+}
+
+/// What the methods of an [`Executor`](crate::Executor) which panic on
+/// failure do instead, as configured by
+/// [`Executor::with_failure_strategy`](crate::Executor::with_failure_strategy).
+#[derive(Clone, Default)]
+pub enum FailureStrategy {
+    /// Panic, with a message describing the failure.
+    #[default]
+    Panic,
+    /// Return the output of a command which ran but did not succeed, as
+    /// though it had succeeded, leaving the caller to check it.
+    ///
+    /// Applies to methods which return the output of commands, including
+    /// commands whose output did not meet the expectations of methods
+    /// such as
+    /// [`Executor::run_expecting_same_stdout`](crate::Executor::run_expecting_same_stdout),
+    /// and pipelines in which any command failed. Failures which leave
+    /// no output to return (such as failures to launch a
+    /// command, or of methods such as
+    /// [`Executor::run_all`](crate::Executor::run_all) which return none)
+    /// still panic.
+    Return,
+    /// Call the handler with the failure, such as to record it in a
+    /// report, or to abort the process. If the handler returns, the
+    /// failure panics as with [`FailureStrategy::Panic`].
+    ///
+    /// Failures of single commands are reported as the error which the
+    /// corresponding `try_` method would have returned. Others are
+    /// reported as [`ExecutionError::Failed`], with the message which
+    /// would have been panicked with. The context label of the executor
+    /// is not included.
+    Custom(Arc<dyn Fn(ExecutionError) + Send + Sync>),
+}
+
+impl fmt::Debug for FailureStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FailureStrategy::Panic => write!(f, "Panic"),
+            FailureStrategy::Return => write!(f, "Return"),
+            FailureStrategy::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+/// A command which failed, among those launched by
+/// [`Executor::run_all_collect`](crate::Executor::run_all_collect).
+#[derive(Debug)]
+pub struct ExecutionFailure {
+    /// The (zero-based) index of the command.
+    pub index: usize,
+    /// The failure of the command, including its output.
+    pub error: ExecutionError,
+}
+
+impl fmt::Display for ExecutionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Command at index {} failed:\n{}", self.index, self.error)
+    }
+}
+
+impl std::error::Error for ExecutionFailure {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl std::error::Error for ExecutionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExecutionError::SpawnFailed { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt;
+
+/// How a launched process is expected to terminate, as checked by
+/// [`Executor::run_expecting`](crate::Executor::run_expecting).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expectation {
+    /// The process exits successfully, as checked by
+    /// [`Executor::run`](crate::Executor::run).
+    Success,
+    /// The process exits with any non-zero exit code, or is terminated
+    /// by a signal.
+    Failure,
+    /// The process exits with exactly the given code, which may be zero.
+    Code(i32),
+    /// The process exits with any of the given codes.
+    Codes(Vec<i32>),
+    /// The process is terminated by the given signal (such as
+    /// `libc::SIGTERM`). Never met on platforms other than Unix.
+    Signal(i32),
+    /// The process exceeds the timeout set with
+    /// [`Executor::with_timeout`](crate::Executor::with_timeout), and is
+    /// killed.
+    Timeout,
+}
+
+/// Describes the expected termination, as in "Expected exit code 1".
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expectation::Success => write!(f, "success"),
+            Expectation::Failure => write!(f, "failure"),
+            Expectation::Code(code) => write!(f, "exit code {}", code),
+            Expectation::Codes(codes) if codes.len() == 1 => write!(f, "exit code {}", codes[0]),
+            Expectation::Codes(codes) => write!(f, "exit code in {:?}", codes),
+            #[cfg(unix)]
+            Expectation::Signal(signal) => match crate::signal_name(*signal) {
+                Some(name) => write!(f, "termination by signal {} ({})", name, signal),
+                None => write!(f, "termination by signal {}", signal),
+            },
+            #[cfg(not(unix))]
+            Expectation::Signal(signal) => write!(f, "termination by signal {}", signal),
+            Expectation::Timeout => write!(f, "a timeout"),
+        }
+    }
+}
@@ -1,19 +1,260 @@
-use anyhow::anyhow;
-use std::ffi::OsStr;
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
-use std::process::Command;
-use std::str::from_utf8;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Panics with a formatted message, prefixed by the executor's context
+/// label (if any).
+macro_rules! fail {
+    ($executor:expr, $($arg:tt)*) => {
+        $executor.panic(format_args!($($arg)*))
+    };
+}
+
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod ci;
+mod color;
+mod completed;
+mod diff;
+#[cfg(feature = "dotenv")]
+mod dotenv;
+mod error;
+mod expectation;
+mod outcome;
+#[cfg(all(unix, feature = "pty"))]
+mod pty;
+#[cfg(unix)]
+mod rlimit;
+mod runner;
+mod running;
+mod script;
+mod shell;
+mod spawn;
+mod stats;
+mod stdio;
+#[cfg(feature = "tracing")]
+mod trace;
+#[cfg(feature = "serde")]
+mod transcript;
+
+pub use ci::CiFlavor;
+pub use color::ColorChoice;
+pub use completed::CompletedExecution;
+pub use error::{ExecutionError, ExecutionFailure, FailureStrategy};
+pub use expectation::Expectation;
+pub use outcome::RunOutcome;
+#[cfg(unix)]
+pub use rlimit::Rlimit;
+pub use runner::{MockRunner, ProcessRunner};
+pub use running::RunningProcess;
+pub use stats::ExecStats;
+pub use stdio::{StdioMode, Stream};
+
+/// How frequently [`Executor::spawn_and_wait_for_port`] attempts to
+/// connect to the port.
+const PORT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The variables which select a locale, other than `LC_ALL` and `LANG`,
+/// which [`Executor::with_locale`] removes.
+const LOCALE_VARS: [&str; 13] = [
+    "LANGUAGE",
+    "LC_ADDRESS",
+    "LC_COLLATE",
+    "LC_CTYPE",
+    "LC_IDENTIFICATION",
+    "LC_MEASUREMENT",
+    "LC_MESSAGES",
+    "LC_MONETARY",
+    "LC_NAME",
+    "LC_NUMERIC",
+    "LC_PAPER",
+    "LC_TELEPHONE",
+    "LC_TIME",
+];
+
+/// Formats a command as a single line, quoting arguments where necessary
+/// so that a POSIX shell would interpret it identically.
+///
+/// This is the format in which commands appear in failure diagnostics.
+/// Arguments which are not valid unicode are converted lossily.
+pub fn command_line<I, S>(args: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<String> = args
+        .into_iter()
+        .map(|arg| shell::quote(&arg.as_ref().to_string_lossy()).into_owned())
+        .collect();
+    args.join(" ")
+}
+
+/// Launches a new subprocess with an executor providing no additional
+/// environment variables, and awaits its completion, returning the
+/// captured output.
+///
+/// Shorthand for `Executor::new(vec![]).run(args)`, for one-off commands.
+///
+/// # Panics
+///
+/// Panics under the same conditions as [`Executor::run`].
+#[track_caller]
+pub fn run<I, S>(args: I) -> RunOutcome
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    Executor::<&str, &str>::new(vec![]).run(args)
+}
 
 /// An execution environment, consisting of environment variables
 /// which are provided on the launch of each new process.
+///
+/// Executors may be cloned, so that a common configuration can be
+/// specialized for individual tests.
+///
+/// The `with_*` methods consume the executor, so specializing a clone
+/// leaves the configuration of the original unchanged, and a shared
+/// executor (such as in a `static`) may be used concurrently by many
+/// tests.
+///
+/// Clones do share some state, rather than copying it, which is updated
+/// as commands are run through shared references:
+///
+/// - the counts accumulated by [`Executor::with_stats`];
+/// - the file written by `with_transcript`, and the sink of
+///   [`Executor::with_stdout_sink`];
+/// - the callbacks provided to [`Executor::with_env_fn`],
+///   [`Executor::with_env_lazy`], [`Executor::with_pre_run_hook`],
+///   [`Executor::with_post_run_hook`], `with_pre_exec` and
+///   [`FailureStrategy::Custom`], along with any state they capture.
+#[derive(Clone)]
 pub struct Executor<K, V>
 where
     K: AsRef<OsStr> + Clone,
     V: AsRef<OsStr> + Clone,
 {
-    env: Vec<(K, V)>,
+    env: Vec<(EnvKey<K>, V)>,
+    /// The prefix added to the keys of variables by [`Executor::with_env`],
+    /// if set.
+    env_prefix: Option<OsString>,
+    removed_env: Vec<OsString>,
+    /// The value of `PATH` provided to launched processes, if set.
+    path: Option<OsString>,
+    /// The locale provided to launched processes, if set.
+    locale: Option<OsString>,
+    /// The variable through which a seed is provided to launched
+    /// processes, and its value, if set.
+    seed: Option<(OsString, u64)>,
+    current_dir: Option<PathBuf>,
+    timeout: Option<Duration>,
+    #[cfg(unix)]
+    timeout_kill: Option<(i32, Duration)>,
+    total_deadline: Option<Duration>,
+    stdin: Option<Vec<u8>>,
+    stdin_file: Option<PathBuf>,
+    stdout: StdioMode,
+    stderr: StdioMode,
+    stdout_append: bool,
+    stderr_append: bool,
+    clear_env: bool,
+    /// Variables inherited from the caller despite `clear_env`.
+    kept_env: Vec<OsString>,
+    retries: usize,
+    retry_delay: Duration,
+    color: ColorChoice,
+    context: Option<String>,
+    dry_run: bool,
+    verbose: bool,
+    /// The CI system for whose logs the output of each command is
+    /// grouped, if any.
+    ci_grouping: Option<CiFlavor>,
+    /// The counts of launches, shared between clones, if accumulated.
+    stats: Option<Arc<Mutex<ExecStats>>>,
+    streaming: bool,
+    max_output_bytes: Option<usize>,
+    /// Set if line endings in captured output are converted to `\n`.
+    normalize_newlines: bool,
+    /// The number of lines of each stream shown in failure diagnostics.
+    output_tail: Option<usize>,
+    inherit_stdio: bool,
+    merge_output: bool,
+    /// Set if writing to stderr fails the command, other than lines
+    /// containing any of `stderr_allow`.
+    strict_stderr: bool,
+    stderr_allow: Vec<String>,
+    /// Set if the values of environment variables are shown when the
+    /// executor is debug-printed.
+    debug_show_values: bool,
+    interpolate_args: bool,
+    /// Set if arguments which are existing relative paths are made
+    /// absolute.
+    canonicalize_path_args: bool,
+    empty_undefined_vars: bool,
+    stdout_sink: Option<Arc<spawn::Sink>>,
+    #[cfg(unix)]
+    new_process_group: bool,
+    #[cfg(unix)]
+    umask: Option<u32>,
+    #[cfg(unix)]
+    rlimits: Vec<(Rlimit, u64, u64)>,
+    #[cfg(unix)]
+    pre_exec: Vec<Arc<Mutex<PreExec>>>,
+    /// Pairs of descriptors of the caller, and the number at which each
+    /// is provided to launched processes.
+    #[cfg(unix)]
+    inherited_fds: Vec<(RawFd, RawFd)>,
+    #[cfg(unix)]
+    uid: Option<u32>,
+    /// Set if processes are launched with `sudo`.
+    #[cfg(unix)]
+    sudo: bool,
+    /// The root directory of launched processes, if changed.
+    #[cfg(unix)]
+    chroot: Option<PathBuf>,
+    #[cfg(unix)]
+    gid: Option<u32>,
+    #[cfg(all(unix, feature = "pty"))]
+    pty: bool,
+    pre_run_hook: Option<Arc<Hook>>,
+    post_run_hook: Option<Arc<PostHook>>,
+    #[cfg(feature = "serde")]
+    transcript: Option<Arc<transcript::Transcript>>,
+    env_fn: Option<Arc<EnvFn>>,
+    /// Variables whose values are computed each time they are provided.
+    lazy_env: Vec<Arc<LazyVar>>,
+    failure_strategy: FailureStrategy,
 }
 
+/// An [`Executor`] whose environment variables are owned strings, for
+/// storing an executor in a struct or returning one from a function.
+pub type StringExecutor = Executor<String, String>;
+
+/// A callback invoked with the full command line of each process.
+type Hook = dyn Fn(&[&OsStr]) + Send + Sync;
+
+/// A callback invoked with the output of each successful process.
+type PostHook = dyn Fn(&Output) + Send + Sync;
+
+/// A callback invoked in each child process before it executes its
+/// program.
+#[cfg(unix)]
+type PreExec = dyn FnMut() -> io::Result<()> + Send;
+
+/// A callback which computes a variable provided to each process.
+type LazyVar = dyn Fn() -> (OsString, OsString) + Send + Sync;
+
+/// A callback which may modify the variables provided to each process.
+type EnvFn = dyn Fn(&mut Vec<(OsString, OsString)>) + Send + Sync;
+
 impl<K, V> Executor<K, V>
 where
     K: AsRef<OsStr> + Clone,
@@ -23,114 +264,6996 @@ where
     ///
     /// All environment variables are provided to processes launched
     /// with the `run` method.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any key contains `=` or a NUL byte, as such variables
+    /// cannot be provided to a process.
+    #[track_caller]
     pub fn new(env: Vec<(K, V)>) -> Self {
-        Executor { env }
+        for (key, _) in &env {
+            validate_key(key.as_ref());
+        }
+        Executor {
+            env: env
+                .into_iter()
+                .map(|(key, value)| (EnvKey::Given(key), value))
+                .collect(),
+            env_prefix: None,
+            removed_env: Vec::new(),
+            path: None,
+            locale: None,
+            seed: None,
+            current_dir: None,
+            timeout: None,
+            #[cfg(unix)]
+            timeout_kill: None,
+            total_deadline: None,
+            stdin: None,
+            stdin_file: None,
+            stdout: StdioMode::Capture,
+            stderr: StdioMode::Capture,
+            stdout_append: false,
+            stderr_append: false,
+            clear_env: false,
+            kept_env: Vec::new(),
+            retries: 0,
+            retry_delay: Duration::default(),
+            color: ColorChoice::default(),
+            context: None,
+            dry_run: false,
+            verbose: false,
+            ci_grouping: None,
+            stats: None,
+            streaming: false,
+            max_output_bytes: None,
+            normalize_newlines: false,
+            output_tail: None,
+            inherit_stdio: false,
+            merge_output: false,
+            strict_stderr: false,
+            stderr_allow: Vec::new(),
+            debug_show_values: false,
+            interpolate_args: false,
+            canonicalize_path_args: false,
+            empty_undefined_vars: false,
+            stdout_sink: None,
+            #[cfg(unix)]
+            new_process_group: false,
+            #[cfg(unix)]
+            umask: None,
+            #[cfg(unix)]
+            rlimits: Vec::new(),
+            #[cfg(unix)]
+            pre_exec: Vec::new(),
+            #[cfg(unix)]
+            inherited_fds: Vec::new(),
+            #[cfg(unix)]
+            uid: None,
+            #[cfg(unix)]
+            sudo: false,
+            #[cfg(unix)]
+            chroot: None,
+            #[cfg(unix)]
+            gid: None,
+            #[cfg(all(unix, feature = "pty"))]
+            pty: false,
+            pre_run_hook: None,
+            post_run_hook: None,
+            #[cfg(feature = "serde")]
+            transcript: None,
+            env_fn: None,
+            lazy_env: Vec::new(),
+            failure_strategy: FailureStrategy::Panic,
+        }
     }
 
-    /// Launches a new subprocess and awaits its completion.
+    /// Initializes a new Executor from any collection of environment
+    /// variables, such as a `HashMap` or `BTreeMap`.
     ///
-    /// Pretty-prints stdout/stderr on failure.
+    /// Variables are provided in iteration order, which for a `BTreeMap`
+    /// is deterministic (sorted by key), so commands are described
+    /// identically across runs. The order of a `HashMap` is arbitrary.
     ///
     /// # Panics
     ///
-    /// This method is a little aggressive about panicking; it
-    /// can totally evolve structured errors if that would be useful.
-    /// However, given that the primary purpose is testing, this
-    /// behavior is *currently* acceptable.
-    ///
-    /// Panics if...
-    /// - `args` is empty.
-    /// - The sub-process fails to execute.
-    /// - The execution of the sub-process returns a non-zero exit code.
-    /// - The sub-process writes invalid UTF-8 stdout/stderr.
-    pub fn run<I, S>(&self, args: I)
+    /// Panics under the same conditions as [`Executor::new`].
+    #[track_caller]
+    pub fn from_map<M>(map: M) -> Self
     where
-        I: IntoIterator<Item = S>,
-        S: AsRef<OsStr>,
+        M: IntoIterator<Item = (K, V)>,
     {
-        Execution::run(args, self.env.clone())
+        Executor::new(map.into_iter().collect())
     }
-}
 
-struct Execution<S: AsRef<OsStr>> {
-    cmd: S,
-    args: Vec<S>,
-    result: Option<std::process::Output>,
-}
+    /// Adds an environment variable provided to launched processes.
+    ///
+    /// Variables are applied in order, so this overrides any earlier
+    /// value provided for `key`. If a prefix was set with
+    /// [`Executor::with_env_prefix`], it is prepended to `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` contains `=` or a NUL byte.
+    #[track_caller]
+    pub fn with_env(mut self, key: K, value: V) -> Self {
+        validate_key(key.as_ref());
+        let key = match &self.env_prefix {
+            Some(prefix) => {
+                let mut prefixed = prefix.clone();
+                prefixed.push(key.as_ref());
+                EnvKey::Prefixed(prefixed)
+            }
+            None => EnvKey::Given(key),
+        };
+        self.env.push((key, value));
+        self
+    }
+
+    /// Prepends `prefix` to the keys of variables subsequently added with
+    /// [`Executor::with_env`], for programs configured by variables
+    /// sharing a prefix (such as `MYAPP_PORT`). Replaces any previously
+    /// set prefix, and an empty `prefix` stops prefixing.
+    ///
+    /// Only variables added after the prefix is set are affected; those
+    /// already configured (including those provided to
+    /// [`Executor::new`]) are not. Prefixed variables are applied in
+    /// order with the others, as with [`Executor::with_env`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix` contains `=` or a NUL byte.
+    #[track_caller]
+    pub fn with_env_prefix(mut self, prefix: &str) -> Self {
+        validate_key(OsStr::new(prefix));
+        self.env_prefix = (!prefix.is_empty()).then(|| prefix.into());
+        self
+    }
+
+    /// Merges the environment variables of `other` into this executor.
+    ///
+    /// Where both executors provide a value for the same key, the value
+    /// from `other` takes precedence, and the value from `self` is dropped.
+    /// Otherwise, variables from `self` are followed by those of `other`,
+    /// in their original order. Keys are compared exactly (so on Windows,
+    /// where keys are case-insensitive, differently-cased duplicates are
+    /// retained, though later entries still win).
+    ///
+    /// Only the environment is merged; all other configuration is that
+    /// of `self`.
+    pub fn merge(mut self, other: Executor<K, V>) -> Self {
+        self.env
+            .retain(|(key, _)| !other.env.iter().any(|(k, _)| k.as_ref() == key.as_ref()));
+        self.env.extend(other.env);
+        self
+    }
+
+    /// Prevents launched processes from inheriting the environment of
+    /// the caller, so they observe *only* the configured variables.
+    ///
+    /// Note that this includes `PATH`, which must then be supplied
+    /// explicitly if needed (or commands must be invoked by absolute path).
+    pub fn clear_env(mut self) -> Self {
+        self.clear_env = true;
+        self
+    }
 
-impl<S: AsRef<OsStr>> Execution<S> {
-    fn run<I, K, V, E>(args: I, envs: E)
+    /// Prevents launched processes from inheriting the environment of
+    /// the caller, other than the variables named by `keys` (such as
+    /// `PATH` and `HOME`), so they observe only those and the configured
+    /// variables.
+    ///
+    /// Variables are inherited if they are set when each process is
+    /// launched. May be called repeatedly to inherit further variables.
+    pub fn keep_only_env<I>(mut self, keys: I) -> Self
     where
-        I: IntoIterator<Item = S>,
-        K: AsRef<OsStr>,
-        V: AsRef<OsStr>,
-        E: IntoIterator<Item = (K, V)>,
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
     {
-        let mut iter = args.into_iter();
-        let mut exec = Execution {
-            cmd: iter
-                .next()
-                .ok_or_else(|| anyhow!("Missing command"))
-                .unwrap(),
-            args: iter.collect::<Vec<S>>(),
-            result: None,
-        };
+        self.clear_env = true;
+        self.kept_env
+            .extend(keys.into_iter().map(|key| key.as_ref().to_os_string()));
+        self
+    }
 
-        exec.result = Some(
-            Command::new(&exec.cmd)
-                .args(&exec.args)
-                .envs(envs)
-                .output()
-                .expect("Failed to execute command"),
-        );
-        assert!(
-            exec.result.as_ref().unwrap().status.success(),
-            format!("{}", exec)
-        );
+    /// Calls `f` to modify the variables provided to each launched process,
+    /// for values which must be computed when the process is launched.
+    ///
+    /// `f` is called with every variable configured for the launch, in
+    /// the order they are applied (so later entries take precedence),
+    /// and may add, remove or modify entries. Variables inherited from
+    /// the caller are not included. As the environment is also assembled
+    /// to interpolate arguments and describe failures, `f` may be called
+    /// more than once per launch. Replaces any previously set function.
+    pub fn with_env_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut Vec<(OsString, OsString)>) + Send + Sync + 'static,
+    {
+        self.env_fn = Some(Arc::new(f));
+        self
     }
-}
 
-impl<S: AsRef<OsStr>> fmt::Display for Execution<S> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut cmd = Vec::new();
-        cmd.push(self.cmd.as_ref());
-        for arg in &self.args {
-            cmd.push(arg.as_ref());
+    /// Calls `f` to compute a variable provided to each launched process,
+    /// for values which must be fresh when the process is launched (such
+    /// as a short-lived token).
+    ///
+    /// The variable is applied after (and so overrides) the variables of
+    /// the executor, in the order such variables were added. As with
+    /// [`Executor::with_env_fn`], `f` may be called more than once per
+    /// launch.
+    ///
+    /// # Panics
+    ///
+    /// Launching a process panics if the key returned by `f` contains `=`
+    /// or a NUL byte.
+    pub fn with_env_lazy<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> (OsString, OsString) + Send + Sync + 'static,
+    {
+        self.lazy_env.push(Arc::new(f));
+        self
+    }
+
+    /// Sets the `PATH` of launched processes to `dirs`, in order.
+    ///
+    /// Programs are also resolved against `dirs`, so combined with
+    /// [`Executor::clear_env`], this limits processes to the tools in the
+    /// given directories. Overrides any `PATH` among the variables of
+    /// the executor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if none of `dirs` exist, or if any contains the separator
+    /// of `PATH` (`:` on Unix, `;` on Windows).
+    #[track_caller]
+    pub fn with_path<I, P>(mut self, dirs: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let dirs: Vec<PathBuf> = dirs.into_iter().map(|d| d.as_ref().to_path_buf()).collect();
+        if !dirs.iter().any(|dir| dir.is_dir()) {
+            panic!("None of the PATH directories exist: {:?}", dirs);
         }
-        let cmd: Vec<String> = cmd
-            .into_iter()
-            .map(|osstr| osstr.to_string_lossy().to_string())
-            .collect();
-        write!(f, "\x1b[95m{}\x1b[0m", cmd.join(" "))?;
-        if let Some(out) = self.result.as_ref() {
-            if !out.status.success() {
-                write!(f, "\n{}", out.status)?;
-            }
-            if !out.stdout.is_empty() {
-                write!(f, "\n\x1b[92m{}\x1b[0m", from_utf8(&out.stdout).unwrap())?;
-            }
-            if !out.stderr.is_empty() {
-                write!(f, "\n\x1b[91m{}\x1b[0m", from_utf8(&out.stderr).unwrap())?;
+        let path = std::env::join_paths(&dirs)
+            .unwrap_or_else(|err| panic!("Invalid PATH directories {:?}: {}", dirs, err));
+        self.path = Some(path);
+        self
+    }
+
+    /// Runs launched processes under `locale` (such as `"C"` or
+    /// `"en_US.UTF-8"`), for stable output from tools which sort, format
+    /// numbers or print dates according to the locale.
+    ///
+    /// Sets `LC_ALL` and `LANG` to `locale`, overriding any among the
+    /// variables of the executor, and removes the other `LC_*` variables
+    /// and `LANGUAGE`, whether inherited or configured. This drops any
+    /// of these set with [`Executor::with_env`], before or after this
+    /// call. Variables provided for a single run, as with
+    /// [`Executor::run_with_env`], still apply. Calling this again
+    /// replaces the locale.
+    pub fn with_locale(mut self, locale: &str) -> Self {
+        self.locale = Some(locale.into());
+        for var in LOCALE_VARS {
+            if !self.removed_env.iter().any(|key| key == var) {
+                self.removed_env.push(var.into());
             }
         }
-        Ok(())
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Provides `seed` to launched processes as the variable `var`, for
+    /// programs which seed a random number generator from it.
+    ///
+    /// The seed is included in the messages of failures, so that they
+    /// can be reproduced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `var` contains `=` or a NUL byte.
+    #[track_caller]
+    pub fn with_seed(mut self, var: &str, seed: u64) -> Self {
+        validate_key(OsStr::new(var));
+        self.seed = Some((var.into(), seed));
+        self
+    }
 
-    #[test]
-    fn export_right_environment_variable() {
-        let executor = Executor::new(vec![("FOO", "BAR")]);
-        executor.run(vec!["/bin/bash", "-c", "[ \"$FOO\" == \"BAR\" ]"]);
+    /// As [`Executor::with_seed`], with a seed chosen at random.
+    ///
+    /// Every process launched by the executor (and its clones) receives
+    /// the same seed. A failure can be reproduced by replacing this with
+    /// [`Executor::with_seed`], using the seed shown in its message.
+    #[track_caller]
+    pub fn with_random_seed(self, var: &str) -> Self {
+        use std::collections::hash_map::RandomState;
+        use std::hash::{BuildHasher, Hasher};
+        let seed = RandomState::new().build_hasher().finish();
+        self.with_seed(var, seed)
     }
 
-    #[test]
-    #[should_panic]
-    fn export_wrong_environment_variable() {
-        let executor = Executor::new(vec![("FOO", "BAZINGA")]);
-        executor.run(vec!["/bin/bash", "-c", "[ \"$FOO\" == \"BAR\" ]"]);
+    /// Prevents launched processes from inheriting the variable `key`
+    /// from the environment of the caller.
+    ///
+    /// Only inherited variables are removed; variables configured on
+    /// the executor are still provided.
+    pub fn without_env<K2: AsRef<OsStr>>(mut self, key: K2) -> Self {
+        self.removed_env.push(key.as_ref().to_os_string());
+        self
+    }
+
+    /// Expands references to environment variables in arguments, as
+    /// `$NAME` or `${NAME}`, before launching processes, without
+    /// involving a shell.
+    ///
+    /// Variables take the value they would have in the environment of
+    /// the launched process, including those inherited from the caller.
+    /// `$$` expands to a single `$`. Referencing a variable which is not
+    /// set is an error, unless [`Executor::with_empty_undefined_vars`]
+    /// is used.
+    pub fn with_arg_interpolation(mut self) -> Self {
+        self.interpolate_args = true;
+        self
+    }
+
+    /// Expands references to undefined variables to an empty string, as
+    /// a shell would, when combined with [`Executor::with_arg_interpolation`].
+    pub fn with_empty_undefined_vars(mut self) -> Self {
+        self.empty_undefined_vars = true;
+        self
+    }
+
+    /// Replaces arguments which are relative paths of existing files (or
+    /// directories), relative to the working directory of the caller,
+    /// with their canonical absolute paths.
+    ///
+    /// Otherwise, relative paths are resolved by the launched process
+    /// relative to its own working directory, which differs from that of
+    /// the caller if set with [`Executor::with_current_dir`]. Since any
+    /// argument which happens to name an existing file is replaced (such
+    /// as a subcommand named like a directory), this must be enabled
+    /// explicitly. The program itself is not replaced.
+    pub fn with_path_arg_canonicalization(mut self) -> Self {
+        self.canonicalize_path_args = true;
+        self
+    }
+
+    /// Sets the working directory of launched processes.
+    ///
+    /// By default, processes inherit the working directory of the caller.
+    pub fn with_current_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.current_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the maximum duration a launched process may run.
+    ///
+    /// Processes which exceed the timeout are killed, and treated as
+    /// failures. Processes which exit, but leave their captured output
+    /// open beyond the timeout (such as to a process they ran in the
+    /// background), are also treated as having timed out, with the output
+    /// read by then. By default, processes may run indefinitely.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sends `signal` (such as `libc::SIGTERM`) to processes which exceed
+    /// the timeout, allowing them to clean up, and only kills those which
+    /// are still running after `grace`.
+    ///
+    /// By default, processes which exceed the timeout are killed
+    /// immediately. Either way, the process is treated as having timed
+    /// out, even if it exits successfully after being signalled.
+    #[cfg(unix)]
+    pub fn with_timeout_kill(mut self, signal: i32, grace: Duration) -> Self {
+        self.timeout_kill = Some((signal, grace));
+        self
+    }
+
+    /// Limits the total duration of the commands launched by
+    /// [`Executor::run_all`] to `deadline`.
+    ///
+    /// Each attempt of each command is given the time remaining until the
+    /// deadline as its timeout (or the timeout set with
+    /// [`Executor::with_timeout`], if sooner), so the batch fails once the
+    /// deadline is exceeded. Commands are not retried once too little time
+    /// remains to wait out the delay set with [`Executor::with_retries`].
+    pub fn with_total_deadline(mut self, deadline: Duration) -> Self {
+        self.total_deadline = Some(deadline);
+        self
+    }
+
+    /// Relaunches commands which fail (or time out) up to `count` more
+    /// times, sleeping for `delay` between attempts.
+    ///
+    /// Applies to [`Executor::run`], [`Executor::try_run`], and other
+    /// methods which expect success. A command is only treated as a
+    /// failure if every attempt fails, in which case the last attempt is
+    /// reported. Commands which cannot be launched at all are not retried.
+    pub fn with_retries(mut self, count: usize, delay: Duration) -> Self {
+        self.retries = count;
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Provides `data` as the standard input of launched processes.
+    ///
+    /// By default, the standard input of launched processes is closed.
+    pub fn with_stdin<B: Into<Vec<u8>>>(mut self, data: B) -> Self {
+        self.stdin = Some(data.into());
+        self.stdin_file = None;
+        self
+    }
+
+    /// Connects the standard input of launched processes to the file at
+    /// `path`, without reading it into memory.
+    ///
+    /// The file is opened for each launch; if it cannot be opened, the
+    /// process is not launched, and an [`ExecutionError::SpawnFailed`]
+    /// describes the file. Replaces any data set with
+    /// [`Executor::with_stdin`].
+    pub fn with_stdin_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stdin_file = Some(path.as_ref().to_path_buf());
+        self.stdin = None;
+        self
+    }
+
+    /// Directs where launched processes write stdout.
+    ///
+    /// By default, stdout is captured. Streams which are not captured
+    /// are absent from returned `Output`s, and from failure diagnostics
+    /// (other than to refer to the file written to, if any).
+    pub fn with_stdout(mut self, mode: StdioMode) -> Self {
+        self.stdout = mode;
+        self.stdout_append = false;
+        self
+    }
+
+    /// Directs where launched processes write stderr.
+    ///
+    /// Identical to [`Executor::with_stdout`], but for stderr.
+    pub fn with_stderr(mut self, mode: StdioMode) -> Self {
+        self.stderr = mode;
+        self.stderr_append = false;
+        self
+    }
+
+    /// Writes the stdout of launched processes to the file at `path`,
+    /// instead of capturing it.
+    ///
+    /// The file is created (or truncated) on each launch. As the output
+    /// is not captured, it is absent from returned `Output`s, and failure
+    /// diagnostics refer to the file instead.
+    pub fn with_stdout_file<P: AsRef<Path>>(self, path: P) -> Self {
+        self.with_stdout(StdioMode::File(path.as_ref().to_path_buf()))
+    }
+
+    /// Appends the stdout of launched processes to the file at `path`,
+    /// instead of capturing it.
+    ///
+    /// Identical to [`Executor::with_stdout_file`], other than preserving
+    /// the existing contents of the file, so that the output of several
+    /// launches accumulates in the order they ran.
+    pub fn with_stdout_file_append<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stdout = StdioMode::File(path.as_ref().to_path_buf());
+        self.stdout_append = true;
+        self
+    }
+
+    /// Copies the stdout of launched processes into `sink` while they run,
+    /// in addition to capturing it.
+    ///
+    /// The sink is shared by every process launched by the executor (and
+    /// its clones), and is written from a background thread. Takes the
+    /// place of echoing stdout with [`Executor::with_streaming`].
+    pub fn with_stdout_sink<W: Write + Send + 'static>(mut self, sink: W) -> Self {
+        self.stdout_sink = Some(Arc::new(Mutex::new(sink)));
+        self
+    }
+
+    /// Writes the stderr of launched processes to the file at `path`,
+    /// instead of capturing it.
+    ///
+    /// The file is created (or truncated) on each launch. As the output
+    /// is not captured, it is absent from returned `Output`s, and failure
+    /// diagnostics refer to the file instead.
+    pub fn with_stderr_file<P: AsRef<Path>>(self, path: P) -> Self {
+        self.with_stderr(StdioMode::File(path.as_ref().to_path_buf()))
+    }
+
+    /// Appends the stderr of launched processes to the file at `path`,
+    /// instead of capturing it.
+    ///
+    /// Identical to [`Executor::with_stderr_file`], other than preserving
+    /// the existing contents of the file, so that the output of several
+    /// launches accumulates in the order they ran.
+    pub fn with_stderr_file_append<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stderr = StdioMode::File(path.as_ref().to_path_buf());
+        self.stderr_append = true;
+        self
+    }
+
+    /// Controls whether the diagnostics printed on failure are colored.
+    ///
+    /// Defaults to [`ColorChoice::Auto`].
+    pub fn with_color(mut self, color: ColorChoice) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Shows the values of environment variables when the executor is
+    /// debug-printed.
+    ///
+    /// By default, only their names are shown, as values may be secrets
+    /// which should not appear in logs.
+    pub fn with_debug_show_values(mut self) -> Self {
+        self.debug_show_values = true;
+        self
+    }
+
+    /// Controls whether each command is printed (to stderr) immediately
+    /// before it is launched, like `set -x` in a shell.
+    ///
+    /// Unlike [`Executor::dry_run`], commands are still launched. Colors
+    /// follow [`Executor::with_color`].
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Echoes the stdout/stderr of launched processes to the stdout/stderr
+    /// of the caller while they run, in addition to capturing it.
+    ///
+    /// Useful for observing the progress of long-running commands. Note
+    /// that the test harness does not capture this output, so it is shown
+    /// even for passing tests.
+    pub fn with_streaming(mut self) -> Self {
+        self.streaming = true;
+        self
+    }
+
+    /// Wraps each launch of a command in a collapsible group of the logs
+    /// of the CI system `flavor`, titled by its command line.
+    ///
+    /// Only output written to the caller's stdout/stderr appears within
+    /// the group, such as with [`Executor::with_streaming`] or
+    /// [`Executor::verbose`]. The group is closed once the command
+    /// completes, so the messages of failures are shown outside of it,
+    /// rather than collapsed. Processes launched with [`Executor::spawn`]
+    /// are not grouped.
+    pub fn with_ci_grouping(mut self, flavor: CiFlavor) -> Self {
+        self.ci_grouping = Some(flavor);
+        self
+    }
+
+    /// Accumulates counts of the commands launched, and their timing,
+    /// for retrieval with [`Executor::stats`], such as to profile the
+    /// setup of a test.
+    ///
+    /// The counts are shared with clones of the executor made after this
+    /// is called, so a common executor accumulates the launches of every
+    /// test specializing it. Every launch is counted, including each
+    /// retry, each stage of [`Executor::pipe`], those of the asynchronous
+    /// methods, and commands which fail to launch at all (as failures).
+    /// Only commands which are not launched (such as those of a dry run),
+    /// and processes launched in the background with [`Executor::spawn`],
+    /// are not counted. Resets any previous counts.
+    pub fn with_stats(mut self) -> Self {
+        self.stats = Some(Arc::default());
+        self
+    }
+
+    /// Returns the counts of commands launched since
+    /// [`Executor::with_stats`] was called, or zero counts if it was not.
+    pub fn stats(&self) -> ExecStats {
+        self.stats
+            .as_ref()
+            .map_or_else(ExecStats::default, |stats| *stats.lock().unwrap())
+    }
+
+    /// Limits the number of bytes of stdout (and, separately, of stderr)
+    /// retained from each launched process.
+    ///
+    /// Processes may still write any amount of output, which is read to
+    /// completion, but bytes beyond the limit are discarded. Failure
+    /// diagnostics note where output was truncated. By default, all
+    /// output is retained.
+    pub fn with_max_output_bytes(mut self, limit: usize) -> Self {
+        self.max_output_bytes = Some(limit);
+        self
+    }
+
+    /// Converts `\r\n` and lone `\r` line endings in captured stdout and
+    /// stderr to `\n`, before they are returned or checked, so comparisons
+    /// of output do not depend on the line endings of the platform.
+    ///
+    /// The bytes written by each process remain available from
+    /// [`RunOutcome::raw_stdout`] and [`RunOutcome::raw_stderr`]. Lines
+    /// yielded while the process runs, such as by
+    /// [`RunningProcess::stdout_lines`], are not normalized.
+    pub fn with_normalize_newlines(mut self) -> Self {
+        self.normalize_newlines = true;
+        self
+    }
+
+    /// Shows only the last `lines` lines of stdout (and, separately, of
+    /// stderr) in failure diagnostics, noting how many earlier lines were
+    /// omitted.
+    ///
+    /// Keeps the diagnostics of chatty commands focused on the end of
+    /// their output, where errors usually appear. All output is still
+    /// captured, and returned by [`Executor::try_run`] and the other
+    /// methods returning output. By default, all output is shown.
+    pub fn with_output_tail(mut self, lines: usize) -> Self {
+        self.output_tail = Some(lines);
+        self
+    }
+
+    /// Writes the stderr of launched processes to the same pipe as their
+    /// stdout, so that the captured stdout contains both, interleaved in
+    /// the order they were written, and the captured stderr is empty.
+    ///
+    /// The exact interleaving depends on the buffering of each stream by
+    /// the process; many programs buffer stdout, but not stderr, when it
+    /// is not a terminal. If stdout is written to a file, so is stderr.
+    /// The output of [`Executor::pipe`] is not merged.
+    pub fn with_merged_output(mut self) -> Self {
+        self.merge_output = true;
+        self
+    }
+
+    /// Treats any output to stderr as a failure of every command run by
+    /// the executor, even if it exits successfully.
+    ///
+    /// Unlike [`Executor::run_expecting_clean_stderr`], this applies to
+    /// every method which checks for success. Only captured stderr is
+    /// checked, so this has no effect if stderr is redirected, inherited
+    /// or merged into stdout.
+    pub fn with_strict_stderr(mut self) -> Self {
+        self.strict_stderr = true;
+        self
+    }
+
+    /// As [`Executor::with_strict_stderr`], but permits lines of stderr
+    /// which contain any of `patterns`, for known and benign warnings.
+    ///
+    /// May be called repeatedly to permit further patterns.
+    pub fn with_stderr_allow<I, P>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        self.strict_stderr = true;
+        self.stderr_allow
+            .extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Connects the stdin, stdout and stderr of launched processes to
+    /// those of the caller, for commands which interact with a terminal.
+    ///
+    /// Overrides [`Executor::with_stdin`], [`Executor::with_stdout`]
+    /// and [`Executor::with_stderr`]. As no output is captured,
+    /// success is determined solely by the exit status.
+    pub fn with_inherited_stdio(mut self) -> Self {
+        self.inherit_stdio = true;
+        self
+    }
+
+    /// Connects the stdout and stderr of launched processes to those of
+    /// the caller, for watching a failing command while debugging it.
+    ///
+    /// Equivalent to inheriting both streams with [`Executor::with_stdout`]
+    /// and [`Executor::with_stderr`]; stdin is still configured as usual.
+    /// The output is written directly to the file descriptors of the test
+    /// process, which the test harness does not capture, so it is shown
+    /// live even without `cargo test -- --nocapture`. Passing
+    /// `--nocapture` (or `--test-threads=1`) keeps it from interleaving
+    /// with the output of other tests. As no output is captured, success
+    /// is determined solely by the exit status.
+    pub fn with_passthrough(self) -> Self {
+        self.with_stdout(StdioMode::Inherit)
+            .with_stderr(StdioMode::Inherit)
+    }
+
+    /// Launches processes in a new process group, rather than that of
+    /// the caller.
+    ///
+    /// Signals delivered to the caller's process group (such as the
+    /// `SIGINT` sent on Ctrl-C) are then not delivered to launched
+    /// processes, which must instead be terminated explicitly.
+    #[cfg(unix)]
+    pub fn with_new_process_group(mut self) -> Self {
+        self.new_process_group = true;
+        self
+    }
+
+    /// Sets the file mode creation mask of launched processes.
+    ///
+    /// By default, processes inherit the umask of the caller, which
+    /// typically depends on the configuration of the user's shell.
+    #[cfg(unix)]
+    pub fn with_umask(mut self, mode: u32) -> Self {
+        self.umask = Some(mode);
+        self
+    }
+
+    /// Limits the consumption of `resource` by launched processes to the
+    /// `soft` limit, which they may raise up to the `hard` limit.
+    ///
+    /// Limits are applied in order, so this overrides any earlier limit
+    /// of `resource`. If a limit cannot be set (for example, if `soft`
+    /// exceeds `hard`, or `hard` exceeds the caller's own hard limit
+    /// without privileges), processes fail to launch.
+    #[cfg(unix)]
+    pub fn with_rlimit(mut self, resource: Rlimit, soft: u64, hard: u64) -> Self {
+        self.rlimits.push((resource, soft, hard));
+        self
+    }
+
+    /// Calls `f` in each launched process, after it is forked from the
+    /// caller and before it executes its program, as with
+    /// `CommandExt::pre_exec`.
+    ///
+    /// An escape hatch for configuration which the executor does not
+    /// otherwise support, such as dropping capabilities. Closures are
+    /// called in the order they were added, after the umask, inherited
+    /// file descriptors and resource limits of the executor have been
+    /// applied. If `f` returns an error, the process fails to launch
+    /// with that error.
+    ///
+    /// Each process calls its own copy of `f`, so changes to the state
+    /// captured by `f` are not observed by the caller, or by other
+    /// processes.
+    ///
+    /// # Safety
+    ///
+    /// `f` runs in the child process, in a state in which only some
+    /// operations are sound:
+    ///
+    /// - If the caller has multiple threads (as test harnesses do), the
+    ///   child is a copy of the calling thread alone, and any lock held
+    ///   by another thread remains held forever. `f` must only call
+    ///   functions which are async-signal-safe (see `signal-safety(7)`),
+    ///   so must not allocate, print, or lock mutexes (including those
+    ///   within the standard library).
+    /// - The child shares file descriptors, but not memory, with the
+    ///   caller. Closing or modifying descriptors which the standard
+    ///   library uses to launch the process, such as its stdio, may make
+    ///   it behave unexpectedly.
+    ///
+    /// See `CommandExt::pre_exec` for further details.
+    #[cfg(unix)]
+    pub unsafe fn with_pre_exec<F>(mut self, f: F) -> Self
+    where
+        F: FnMut() -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.pre_exec.push(Arc::new(Mutex::new(f)));
+        self
+    }
+
+    /// Provides the file descriptor `fd` of the caller to launched
+    /// processes, as the descriptor `child_fd`.
+    ///
+    /// This is intended for tools which expect pre-opened descriptors,
+    /// such as sockets passed by a supervisor. The descriptor is
+    /// duplicated in each process just before it executes, so `fd` must
+    /// remain open (and refer to the intended file) for as long as
+    /// processes are launched by the executor; the executor does not take
+    /// ownership of it. Descriptors are provided in order, so `child_fd`
+    /// should not be the `fd` of a later call. If `fd` is not open,
+    /// processes fail to launch.
+    #[cfg(unix)]
+    pub fn with_inherited_fd(mut self, fd: RawFd, child_fd: RawFd) -> Self {
+        self.inherited_fds.push((fd, child_fd));
+        self
+    }
+
+    /// Launches processes as the user `uid`.
+    ///
+    /// This typically requires the caller to run as root; otherwise,
+    /// processes fail to launch with a permission error.
+    #[cfg(unix)]
+    pub fn with_uid(mut self, uid: u32) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Launches processes as the group `gid`.
+    ///
+    /// This typically requires the caller to run as root; otherwise,
+    /// processes fail to launch with a permission error.
+    #[cfg(unix)]
+    pub fn with_gid(mut self, gid: u32) -> Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Launches processes with `sudo -n`, as root.
+    ///
+    /// Since `sudo` typically resets the environment, the variables
+    /// provided by the executor are set for `sudo` and named with
+    /// `--preserve-env`, rather than passed on its command line (where
+    /// other users could read their values). The sudo policy must permit
+    /// preserving them, as it does for commands allowed by `ALL` or
+    /// tagged `SETENV`. Other variables are as determined by the policy.
+    ///
+    /// `sudo` is run non-interactively, so fails rather than prompting
+    /// if a password is required. This failure is reported as requiring
+    /// passwordless sudo, rather than as a failure of the command, if
+    /// stderr is captured (as it is unless configured otherwise with
+    /// [`Executor::with_stderr`]). Tests using this should be skipped in
+    /// environments without passwordless sudo.
+    #[cfg(unix)]
+    pub fn with_sudo(mut self) -> Self {
+        self.sudo = true;
+        self
+    }
+
+    /// Launches processes confined to the root directory `dir`, with
+    /// `chroot`, such as to test installation into a prepared root
+    /// filesystem.
+    ///
+    /// The program, and anything it uses (such as shared libraries, or
+    /// the directories of `PATH`), must exist within `dir`, and should be
+    /// given by its absolute path within it. The working directory set
+    /// with [`Executor::with_current_dir`] is also within `dir`, which is
+    /// its default. Failures to change the root, or to find the program
+    /// within it, are reported as failures to launch.
+    ///
+    /// This typically requires the caller to run as root, and cannot be
+    /// combined with [`Executor::with_uid`], since the root is changed
+    /// after the uid.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dir` contains a NUL byte.
+    #[cfg(unix)]
+    #[track_caller]
+    pub fn with_chroot<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        let dir = dir.as_ref();
+        if c_path(dir).is_none() {
+            fail!(
+                self,
+                "Invalid root directory {:?}: contains a NUL byte",
+                dir
+            );
+        }
+        self.chroot = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Connects the stdout and stderr of launched processes to a new
+    /// pseudo-terminal, for testing behavior which only occurs when
+    /// writing to a terminal (such as colors or progress bars).
+    ///
+    /// Everything written to the terminal is captured as stdout, and
+    /// stderr is empty. Output is as a terminal would receive it, so
+    /// line endings are `\r\n`. The terminal is 80 columns wide and 24
+    /// rows high, and becomes the controlling terminal of the process
+    /// (`/dev/tty`), unless it leads a process group. Stdin is still
+    /// provided as configured.
+    ///
+    /// Overrides [`Executor::with_stdout`], [`Executor::with_stderr`]
+    /// and [`Executor::with_merged_output`]. The output of
+    /// [`Executor::pipe`] is not written to a terminal. Only available
+    /// on Unix, with the `pty` feature.
+    #[cfg(all(unix, feature = "pty"))]
+    pub fn with_pty(mut self) -> Self {
+        self.pty = true;
+        self
+    }
+
+    /// Calls `hook` with the full command line (including the command
+    /// itself) immediately before each process is launched.
+    ///
+    /// The hook is called once per launch, so commands which are retried
+    /// are reported for each attempt, and commands which are not launched
+    /// (such as those of a dry run) are not reported. Replaces any
+    /// previously set hook.
+    pub fn with_pre_run_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&[&OsStr]) + Send + Sync + 'static,
+    {
+        self.pre_run_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Calls `hook` with the captured output of each process which
+    /// completes successfully, such as to collect output for a report.
+    ///
+    /// The hook is not called for failed attempts (which are either
+    /// retried or reported as failures), for commands which are not
+    /// launched, or for processes launched with [`Executor::spawn`]. It
+    /// cannot modify the output. Replaces any previously set hook.
+    pub fn with_post_run_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Output) + Send + Sync + 'static,
+    {
+        self.post_run_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Appends a record of each launch of a command to the file at
+    /// `path`, as a line of JSON, for inspecting nondeterministic
+    /// failures.
+    ///
+    /// Each record includes the command line, the variables provided by
+    /// the executor, the working directory (if set), the attempt number
+    /// and duration, and either how the command terminated and its
+    /// captured output, or why it failed to launch. As when the executor
+    /// is debug-printed, the values of variables are redacted, as they
+    /// may be secrets, unless enabled by
+    /// [`Executor::with_debug_show_values`]; captured output is recorded
+    /// as written.
+    ///
+    /// Records are written as each launch completes, so a crashing test
+    /// leaves those before it. Every launch is recorded, including each
+    /// retry, each stage of [`Executor::pipe`], and those of the
+    /// asynchronous methods. Only commands which are not launched (such
+    /// as those of a dry run), and processes launched in the background
+    /// with [`Executor::spawn`], are not recorded.
+    /// Failures to write records are ignored. Replaces any previously
+    /// set transcript.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file cannot be opened for appending.
+    #[cfg(feature = "serde")]
+    #[track_caller]
+    pub fn with_transcript<P: AsRef<Path>>(mut self, path: P) -> Self {
+        let path = path.as_ref();
+        let transcript = match transcript::Transcript::open(path) {
+            Ok(transcript) => transcript,
+            Err(err) => fail!(
+                self,
+                "Failed to open transcript {}: {}",
+                path.display(),
+                err
+            ),
+        };
+        self.transcript = Some(Arc::new(transcript));
+        self
+    }
+
+    /// Labels commands launched by the executor, to identify them in
+    /// failure diagnostics.
+    ///
+    /// Panic messages are prefixed with `[label]`, and `tracing` spans
+    /// (with the `tracing` feature) carry the label as `context`.
+    pub fn with_context<L: Into<String>>(mut self, label: L) -> Self {
+        self.context = Some(label.into());
+        self
+    }
+
+    /// Determines what methods which panic on failure (such as
+    /// [`Executor::run`]) do instead, such as calling a handler which
+    /// records the failure in a report.
+    ///
+    /// Methods returning a `Result` (such as [`Executor::try_run`]) are
+    /// unaffected. By default, failures panic.
+    pub fn with_failure_strategy(mut self, strategy: FailureStrategy) -> Self {
+        self.failure_strategy = strategy;
+        self
+    }
+
+    /// Prints commands (to stderr) instead of launching them.
+    ///
+    /// Each command is printed along with the configured environment
+    /// and working directory, and then treated as having succeeded
+    /// without any output; no assertions are made about its outcome.
+    ///
+    /// Only methods which await the completion of a command are affected,
+    /// so [`Executor::spawn`] still launches processes.
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// Pretty-prints stdout/stderr on failure.
+    ///
+    /// # Panics
+    ///
+    /// This method is a little aggressive about panicking; use
+    /// [`Executor::try_run`] to receive structured errors instead.
+    /// However, given that the primary purpose is testing, this
+    /// behavior is *currently* acceptable.
+    ///
+    /// Panics if...
+    /// - `args` is empty.
+    /// - The sub-process fails to execute.
+    /// - The execution of the sub-process returns a non-zero exit code.
+    /// - The sub-process exceeds the timeout, if one is set.
+    ///
+    /// The panic is reported at the location of the caller.
+    #[track_caller]
+    pub fn run<I, S>(&self, args: I) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.run_successfully(args).into_outcome()
+    }
+
+    /// Launches `program` with the arguments `args`, as with
+    /// [`Executor::run`].
+    ///
+    /// Mirrors `Command::new(program).args(args)`, for callers which hold
+    /// the program and its arguments separately.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`Executor::run`].
+    #[track_caller]
+    pub fn run_program<P, I, S>(&self, program: P, args: I) -> RunOutcome
+    where
+        P: AsRef<OsStr>,
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args = args.into_iter().map(|arg| arg.as_ref().to_os_string());
+        self.run(std::iter::once(program.as_ref().to_os_string()).chain(args))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// Identical to [`Executor::run`], other than additionally providing
+    /// `extra_env` to the sub-process. These variables are applied after
+    /// (and so override) those of the executor, which is left unmodified.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any key of `extra_env` contains `=` or a NUL byte, or
+    /// under any of the conditions which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_with_env<I, S, EI, EK, EV>(&self, args: I, extra_env: EI) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        EI: IntoIterator<Item = (EK, EV)>,
+        EK: AsRef<OsStr>,
+        EV: AsRef<OsStr>,
+    {
+        let exec = self.prepare_with_env(args, extra_env).or_fail(self);
+        match self.checked_output(exec) {
+            Ok(exec) => exec.into_outcome(),
+            Err(msg) => fail!(self, "{}", msg),
+        }
+    }
+
+    /// Launches a new subprocess in `dir` and awaits its completion,
+    /// returning the captured output.
+    ///
+    /// Identical to [`Executor::run`], other than running the sub-process
+    /// in `dir`, in place of any directory set with
+    /// [`Executor::with_current_dir`]. The executor is left unmodified.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`Executor::run`].
+    #[track_caller]
+    pub fn run_in<P, I, S>(&self, dir: P, args: I) -> RunOutcome
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut exec = self.prepare(args).or_fail(self);
+        exec.current_dir = Some(dir.as_ref().to_path_buf());
+        match self.checked_output(exec) {
+            Ok(exec) => exec.into_outcome(),
+            Err(msg) => fail!(self, "{}", msg),
+        }
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// Identical to [`Executor::run`], other than requiring the sub-process
+    /// to complete within `budget`, in place of any timeout set with
+    /// [`Executor::with_timeout`]. Processes which exceed the budget are
+    /// killed, as with a timeout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sub-process exceeds `budget`, with a message which
+    /// distinguishes this from other failures, or under any of the
+    /// conditions which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_within<I, S>(&self, args: I, budget: Duration) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut exec = self.prepare(args).or_fail(self);
+        exec.timeout = Some(budget);
+        exec.execute_until_success(self).or_fail(self);
+        if exec.timed_out.is_some() {
+            if let FailureStrategy::Return = self.failure_strategy {
+                return exec.into_outcome();
+            }
+            fail!(self, "Exceeded time budget of {:?}\n{}", budget, exec);
+        }
+        self.succeeded(exec).into_outcome()
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], the
+    /// sub-process is expected to take between `min` and `max`
+    /// (inclusive) to complete, as timed by [`Executor::run_timed`]. A
+    /// command completing too quickly may not have done the expected work,
+    /// such as if a cache was unexpectedly used.
+    ///
+    /// Unlike [`Executor::run_within`], a process exceeding `max` is not
+    /// killed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min` exceeds `max`, if the sub-process completes in
+    /// less than `min` or more than `max`, with a message which
+    /// distinguishes the two, or under any of the conditions which cause
+    /// [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_expecting_duration<I, S>(&self, args: I, min: Duration, max: Duration) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expected(self.expecting_duration(args, min, max))
+    }
+
+    /// Identical to [`Executor::run_expecting_duration`], other than
+    /// returning an error in place of panicking.
+    ///
+    /// An invalid range is reported as [`ExecutionError::Failed`].
+    pub fn try_run_expecting_duration<I, S>(
+        &self,
+        args: I,
+        min: Duration,
+        max: Duration,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Unmet::into_result(self.expecting_duration(args, min, max))
+    }
+
+    fn expecting_duration<I, S>(
+        &self,
+        args: I,
+        min: Duration,
+        max: Duration,
+    ) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        if min > max {
+            return Err(Unmet::Error(ExecutionError::Failed {
+                message: format!("Invalid duration range: {:?} exceeds {:?}", min, max),
+            }));
+        }
+        self.expecting(args, |exec| {
+            let elapsed = exec.elapsed?;
+            if elapsed < min {
+                Some(format!(
+                    "Completed too quickly: took {:?}, less than the minimum of {:?}",
+                    elapsed, min
+                ))
+            } else if elapsed > max {
+                Some(format!(
+                    "Completed too slowly: took {:?}, more than the maximum of {:?}",
+                    elapsed, max
+                ))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Launches the command repeatedly, every `interval`, until it
+    /// succeeds, returning the captured output of the successful launch.
+    ///
+    /// For waiting on a condition checked by the command, such as a
+    /// service becoming healthy. Unlike retries (as configured with
+    /// [`Executor::with_retries`]), failed attempts are expected, so are
+    /// not reported. Each attempt is subject to the executor's timeout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the command has not succeeded once `timeout` has
+    /// elapsed, showing the output of the final attempt, or if `args` is
+    /// empty, or if the sub-process fails to execute.
+    #[track_caller]
+    pub fn run_until_success<I, S>(
+        &self,
+        args: I,
+        interval: Duration,
+        timeout: Duration,
+    ) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let started = Instant::now();
+        let mut exec = self.prepare(args).or_fail(self);
+        loop {
+            exec.execute(self).or_fail(self);
+            if exec.succeeded() {
+                return exec.into_outcome();
+            }
+            let remaining = timeout.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                break;
+            }
+            std::thread::sleep(interval.min(remaining));
+        }
+        if let FailureStrategy::Return = self.failure_strategy {
+            return exec.into_outcome();
+        }
+        let msg = format!(
+            "Did not succeed within {:?}, after {} attempts; the last attempt was:\n{}",
+            timeout,
+            exec.attempts,
+            failure_message(&exec)
+        );
+        self.fail_with(exec.into_failure(), format_args!("{}", msg))
+    }
+
+    /// Splits `command` into arguments, and launches it with [`Executor::run`].
+    ///
+    /// Arguments are split on whitespace, respecting single/double quotes
+    /// and backslash escapes, as a POSIX shell would. No other shell
+    /// features (variables, globs, pipes, etc) are supported.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `command` contains unbalanced quotes, or under any of
+    /// the conditions which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_str(&self, command: &str) -> RunOutcome {
+        let args = match shell::split(command) {
+            Ok(args) => args,
+            Err(err) => fail!(self, "Cannot split command `{}`: {}", command, err),
+        };
+        self.run(args)
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// its stdout as a string, without any trailing newlines.
+    ///
+    /// Intended for commands which print a single value, such as a path
+    /// or version.
+    ///
+    /// # Panics
+    ///
+    /// Panics if stdout is not valid UTF-8, or under any of the conditions
+    /// which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_stdout<I, S>(&self, args: I) -> String
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let exec = self.run_successfully(args);
+        match std::str::from_utf8(&exec.output().stdout) {
+            Ok(stdout) => stdout.trim_end_matches(&['\n', '\r'][..]).to_string(),
+            Err(err) => fail!(self, "Expected stdout to be valid UTF-8: {}\n{}", err, exec),
+        }
+    }
+
+    /// Identical to [`Executor::run_stdout`], other than returning an
+    /// error in place of panicking.
+    ///
+    /// If stdout is not valid UTF-8, returns
+    /// [`ExecutionError::InvalidUtf8`], from which the valid prefix of
+    /// stdout may be recovered.
+    pub fn try_run_stdout<I, S>(&self, args: I) -> Result<String, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let stdout = self.try_run(args)?.stdout;
+        let stdout = String::from_utf8(stdout)
+            .map_err(|err| ExecutionError::invalid_utf8(Stream::Stdout, err.into_bytes()))?;
+        Ok(stdout.trim_end_matches(&['\n', '\r'][..]).to_string())
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning its
+    /// stdout with each of `redactions` applied in turn.
+    ///
+    /// Every match of each pattern is replaced, with the replacement
+    /// expanded as in `Regex::replace_all` (so `$1` refers to a capture
+    /// group). Intended for scrubbing timestamps, paths, and other
+    /// unstable values from output before comparing it to a snapshot.
+    ///
+    /// # Panics
+    ///
+    /// Panics if stdout is not valid UTF-8, or under any of the conditions
+    /// which cause [`Executor::run`] to panic.
+    #[cfg(feature = "regex")]
+    #[track_caller]
+    pub fn run_capture_redacted<I, S>(&self, args: I, redactions: &[(regex::Regex, &str)]) -> String
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let exec = self.run_successfully(args);
+        let stdout = match std::str::from_utf8(&exec.output().stdout) {
+            Ok(stdout) => stdout.to_string(),
+            Err(err) => fail!(self, "Expected stdout to be valid UTF-8: {}\n{}", err, exec),
+        };
+        redactions
+            .iter()
+            .fold(stdout, |stdout, (pattern, replacement)| {
+                pattern.replace_all(&stdout, *replacement).into_owned()
+            })
+    }
+
+    /// Launches a new subprocess and awaits its completion, deserializing
+    /// its stdout as JSON.
+    ///
+    /// # Panics
+    ///
+    /// Panics if stdout cannot be deserialized as a `T`, or under any of
+    /// the conditions which cause [`Executor::run`] to panic.
+    #[cfg(feature = "serde")]
+    #[track_caller]
+    pub fn run_json<T, I, S>(&self, args: I) -> T
+    where
+        T: serde::de::DeserializeOwned,
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let exec = self.run_successfully(args);
+        match serde_json::from_slice(&exec.output().stdout) {
+            Ok(value) => value,
+            Err(err) => fail!(
+                self,
+                "Failed to parse stdout as {}: {}\n{}",
+                std::any::type_name::<T>(),
+                err,
+                exec
+            ),
+        }
+    }
+
+    /// Runs `script` with the platform's shell, using [`Executor::run`].
+    ///
+    /// The script is run with `cmd /C` on Windows, and `/bin/sh -c`
+    /// elsewhere, so it must be written in the syntax of that shell.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`Executor::run`].
+    #[track_caller]
+    pub fn run_shell(&self, script: &str) -> RunOutcome {
+        self.run(shell::command(script))
+    }
+
+    /// Runs `script` with the shell at `shell_path`, as
+    /// `shell_path -c script`, using [`Executor::run`].
+    ///
+    /// For testing behavior specific to a shell (such as `dash`, `zsh`
+    /// or `fish`), where [`Executor::run_shell`] is insufficient. The
+    /// shell must accept a script with `-c`, as POSIX shells do.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`Executor::run`].
+    #[track_caller]
+    pub fn run_with_shell(&self, shell_path: &str, script: &str) -> RunOutcome {
+        self.run([shell_path, "-c", script])
+    }
+
+    /// Writes `script` to a temporary file, and runs it with
+    /// `interpreter` using [`Executor::run`].
+    ///
+    /// The path of the file is provided as the only argument to
+    /// `interpreter`. On Unix, the file is executable, and accessible only
+    /// by the current user. It is removed once the command completes,
+    /// including if it fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file cannot be written, or under the same conditions
+    /// as [`Executor::run`].
+    #[track_caller]
+    pub fn run_script(&self, interpreter: &str, script: &str) -> RunOutcome {
+        let file = match script::TempScript::new(script) {
+            Ok(file) => file,
+            Err(err) => fail!(self, "Failed to write script: {}", err),
+        };
+        self.run(vec![OsStr::new(interpreter), file.path().as_os_str()])
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output on success.
+    ///
+    /// Fails under the same conditions which cause [`Executor::run`] to panic.
+    pub fn try_run<I, S>(&self, args: I) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.prepare(args)?.run(self)
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning the
+    /// captured output without checking how it terminated.
+    ///
+    /// For callers which branch on the outcome themselves. The command is
+    /// launched once, without retries. A command which exceeds the
+    /// timeout is killed, and its output up to that point returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `args` is empty, or if the sub-process fails to execute.
+    #[track_caller]
+    pub fn run_observing<I, S>(&self, args: I) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut exec = self.prepare(args).or_fail(self);
+        exec.execute(self).or_fail(self);
+        exec.into_outcome()
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning a
+    /// description of the execution, whether or not it succeeded.
+    ///
+    /// This exposes the data from which failure diagnostics are built,
+    /// for callers which check or format the result themselves. The
+    /// command is retried, if retries are configured, until it succeeds.
+    ///
+    /// Fails if `args` is empty, or if the sub-process fails to execute.
+    pub fn execute<I, S>(&self, args: I) -> Result<CompletedExecution, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut exec = self.prepare(args)?;
+        exec.execute_until_success(self)?;
+        Ok(CompletedExecution::new(exec))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output, after calling `configure` to customize the
+    /// `Command` for this run.
+    ///
+    /// `configure` is called once the command has been configured by the
+    /// executor, so may override its working directory or environment,
+    /// or set any other attribute without a dedicated builder method. It
+    /// is called before each launch, if the command is retried. Stdin,
+    /// stdout and stderr are connected afterwards, as configured on the
+    /// executor, so that output is still captured.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`Executor::run`].
+    #[track_caller]
+    pub fn run_with<I, S, F>(&self, args: I, configure: F) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        F: Fn(&mut Command),
+    {
+        let mut exec = self.prepare(args).or_fail(self);
+        exec.execute_until_success_with(self, &configure)
+            .or_fail(self);
+        self.succeeded(exec).into_outcome()
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// Unlike [`Executor::run`], the sub-process is expected to terminate
+    /// as described by `expectation`. Other than with
+    /// [`Expectation::Success`], the command is not retried, and is only
+    /// expected to time out with [`Expectation::Timeout`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if...
+    /// - `args` is empty.
+    /// - The sub-process fails to execute.
+    /// - The sub-process does not terminate as expected.
+    #[track_caller]
+    pub fn run_expecting<I, S>(&self, args: I, expectation: Expectation) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expected(self.expecting_status(args, &expectation))
+    }
+
+    /// Identical to [`Executor::run_expecting`], other than returning an
+    /// error in place of panicking.
+    pub fn try_run_expecting<I, S>(
+        &self,
+        args: I,
+        expectation: Expectation,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Unmet::into_result(self.expecting_status(args, &expectation))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// Unlike [`Executor::run`], the sub-process is expected to exit with
+    /// exactly `code`, which may be non-zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if...
+    /// - `args` is empty.
+    /// - The sub-process fails to execute.
+    /// - The sub-process exits with any code other than `code`.
+    /// - The sub-process is terminated by a signal.
+    /// - The sub-process exceeds the timeout, if one is set.
+    #[track_caller]
+    pub fn run_expecting_code<I, S>(&self, args: I, code: i32) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.run_expecting(args, Expectation::Code(code))
+    }
+
+    /// Identical to [`Executor::run_expecting_code`], other than returning
+    /// an error in place of panicking.
+    pub fn try_run_expecting_code<I, S>(
+        &self,
+        args: I,
+        code: i32,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.try_run_expecting(args, Expectation::Code(code))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// Identical to [`Executor::run_expecting_code`], other than accepting
+    /// an exit code which is any of `allowed`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sub-process exits with any code not in `allowed`, or
+    /// under any of the conditions which cause
+    /// [`Executor::run_expecting_code`] to panic.
+    #[track_caller]
+    pub fn run_expecting_codes<I, S>(&self, args: I, allowed: &[i32]) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.run_expecting(args, Expectation::Codes(allowed.to_vec()))
+    }
+
+    /// Identical to [`Executor::run_expecting_codes`], other than returning
+    /// an error in place of panicking.
+    pub fn try_run_expecting_codes<I, S>(
+        &self,
+        args: I,
+        allowed: &[i32],
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.try_run_expecting(args, Expectation::Codes(allowed.to_vec()))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// The inverse of [`Executor::run`]: the sub-process is expected to
+    /// fail, with any non-zero exit code (or by being terminated by a
+    /// signal), so that its diagnostics may be inspected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if...
+    /// - `args` is empty.
+    /// - The sub-process fails to execute.
+    /// - The sub-process exits successfully.
+    /// - The sub-process exceeds the timeout, if one is set.
+    #[track_caller]
+    pub fn run_expecting_failure<I, S>(&self, args: I) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.run_expecting(args, Expectation::Failure)
+    }
+
+    /// Identical to [`Executor::run_expecting_failure`], other than
+    /// returning an error in place of panicking.
+    pub fn try_run_expecting_failure<I, S>(&self, args: I) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.try_run_expecting(args, Expectation::Failure)
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], stdout is
+    /// expected to contain `needle`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if stdout does not contain `needle`, or under any of the
+    /// conditions which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_expecting_stdout_contains<I, S>(&self, args: I, needle: &str) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expected(self.expecting_stdout_contains(args, needle))
+    }
+
+    /// Identical to [`Executor::run_expecting_stdout_contains`], other
+    /// than returning an error in place of panicking.
+    pub fn try_run_expecting_stdout_contains<I, S>(
+        &self,
+        args: I,
+        needle: &str,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Unmet::into_result(self.expecting_stdout_contains(args, needle))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], stdout is
+    /// expected to be exactly `expected`, byte for byte.
+    ///
+    /// # Panics
+    ///
+    /// Panics if stdout differs from `expected`, or under any of the
+    /// conditions which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_expecting_stdout_eq<I, S, B>(&self, args: I, expected: B) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        B: AsRef<[u8]>,
+    {
+        self.expected(self.expecting_stdout_eq(args, expected.as_ref()))
+    }
+
+    /// Identical to [`Executor::run_expecting_stdout_eq`], other than
+    /// returning an error in place of panicking.
+    pub fn try_run_expecting_stdout_eq<I, S, B>(
+        &self,
+        args: I,
+        expected: B,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        B: AsRef<[u8]>,
+    {
+        Unmet::into_result(self.expecting_stdout_eq(args, expected.as_ref()))
+    }
+
+    /// Launches the commands `a` and `b` in turn, and awaits their
+    /// completion, returning their captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`] of each, their
+    /// stdout is expected to be identical, byte for byte. For comparing a
+    /// reimplementation against a reference tool.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stdout of the commands differ, showing both command
+    /// lines and a diff of their output, or under any of the conditions
+    /// which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_expecting_same_stdout<I, S>(&self, a: I, b: I) -> (RunOutcome, RunOutcome)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.same_stdout(a, b, None)
+    }
+
+    /// Identical to [`Executor::run_expecting_same_stdout`], other than
+    /// returning an error in place of panicking.
+    ///
+    /// A difference in stdout is reported as [`ExecutionError::Failed`],
+    /// describing both commands and the diff.
+    pub fn try_run_expecting_same_stdout<I, S>(
+        &self,
+        a: I,
+        b: I,
+    ) -> Result<(RunOutcome, RunOutcome), ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.try_same_stdout(a, b, None)
+    }
+
+    /// Identical to [`Executor::run_expecting_same_stdout`], other than
+    /// comparing the stdout of the commands after applying `normalize`,
+    /// such as to ignore trailing whitespace.
+    ///
+    /// Stdout is converted to a string lossily before being normalized.
+    /// The returned output is not normalized.
+    #[track_caller]
+    pub fn run_expecting_same_stdout_normalized<I, S, F>(
+        &self,
+        a: I,
+        b: I,
+        normalize: F,
+    ) -> (RunOutcome, RunOutcome)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        F: Fn(&str) -> String,
+    {
+        self.same_stdout(a, b, Some(&normalize))
+    }
+
+    /// Identical to [`Executor::run_expecting_same_stdout_normalized`],
+    /// other than returning an error in place of panicking, as
+    /// [`Executor::try_run_expecting_same_stdout`].
+    pub fn try_run_expecting_same_stdout_normalized<I, S, F>(
+        &self,
+        a: I,
+        b: I,
+        normalize: F,
+    ) -> Result<(RunOutcome, RunOutcome), ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        F: Fn(&str) -> String,
+    {
+        self.try_same_stdout(a, b, Some(&normalize))
+    }
+
+    /// Launches the command twice in turn, and awaits the completion of
+    /// each, returning the captured output of both.
+    ///
+    /// For checking that a command (such as a provisioning or migration
+    /// tool) is idempotent: that running it again, once its changes have
+    /// been made, still succeeds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either run fails, under any of the conditions which cause
+    /// [`Executor::run`] to panic, identifying which run failed.
+    #[track_caller]
+    pub fn run_expecting_idempotent<I, S>(&self, args: I) -> (RunOutcome, RunOutcome)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.idempotent(args, None)
+    }
+
+    /// Identical to [`Executor::run_expecting_idempotent`], other than
+    /// returning the error of the first run which failed in place of
+    /// panicking.
+    pub fn try_run_expecting_idempotent<I, S>(
+        &self,
+        args: I,
+    ) -> Result<(RunOutcome, RunOutcome), ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.try_idempotent(args, None)
+    }
+
+    /// As [`Executor::run_expecting_idempotent`], additionally expecting
+    /// the stdout of the second run to be exactly `expected`, such as a
+    /// report that nothing changed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stdout of the second run differs from `expected`, or
+    /// under any of the conditions which cause
+    /// [`Executor::run_expecting_idempotent`] to panic.
+    #[track_caller]
+    pub fn run_expecting_idempotent_with_stdout<I, S, B>(
+        &self,
+        args: I,
+        expected: B,
+    ) -> (RunOutcome, RunOutcome)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        B: AsRef<[u8]>,
+    {
+        self.idempotent(args, Some(expected.as_ref()))
+    }
+
+    /// Identical to [`Executor::run_expecting_idempotent_with_stdout`],
+    /// other than returning an error in place of panicking.
+    ///
+    /// Unexpected stdout of the second run is reported as
+    /// [`ExecutionError::Unexpected`].
+    pub fn try_run_expecting_idempotent_with_stdout<I, S, B>(
+        &self,
+        args: I,
+        expected: B,
+    ) -> Result<(RunOutcome, RunOutcome), ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        B: AsRef<[u8]>,
+    {
+        self.try_idempotent(args, Some(expected.as_ref()))
+    }
+
+    /// Launches the command and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], the lines of
+    /// stdout are expected to be exactly `expected`. Lines are compared
+    /// without their line endings, so a missing trailing newline (or a
+    /// `\r\n` line ending) is not considered a difference.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lines of stdout differ from `expected`, identifying
+    /// the first line which differs, or under any of the conditions which
+    /// cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_expecting_stdout_lines<I, S, L, T>(&self, args: I, expected: L) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        L: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.expected(self.expecting_stdout_lines(args, expected))
+    }
+
+    /// Identical to [`Executor::run_expecting_stdout_lines`], other than
+    /// returning an error in place of panicking.
+    pub fn try_run_expecting_stdout_lines<I, S, L, T>(
+        &self,
+        args: I,
+        expected: L,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        L: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        Unmet::into_result(self.expecting_stdout_lines(args, expected))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], stdout is
+    /// expected to consist of exactly `expected` lines. As with
+    /// [`Executor::run_expecting_stdout_lines`], a trailing newline ends
+    /// the final line, rather than beginning an empty one, so `"a\nb\n"`
+    /// and `"a\nb"` both have two lines.
+    ///
+    /// # Panics
+    ///
+    /// Panics if stdout has a different number of lines, or under any of
+    /// the conditions which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_expecting_line_count<I, S>(&self, args: I, expected: usize) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expected(self.expecting_line_count(args, expected))
+    }
+
+    /// Identical to [`Executor::run_expecting_line_count`], other than
+    /// returning an error in place of panicking.
+    pub fn try_run_expecting_line_count<I, S>(
+        &self,
+        args: I,
+        expected: usize,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Unmet::into_result(self.expecting_line_count(args, expected))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], stdout is
+    /// expected to match the contents of the `golden` file.
+    ///
+    /// If the `UPDATE_GOLDEN` environment variable is set (to anything
+    /// other than `0`), the golden file is instead overwritten with
+    /// stdout, creating it if necessary.
+    ///
+    /// # Panics
+    ///
+    /// Panics if stdout differs from the golden file, showing a diff of
+    /// the two, if the golden file cannot be read or updated, or under
+    /// any of the conditions which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_matching_file<I, S, P>(&self, args: I, golden: P) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        P: AsRef<Path>,
+    {
+        self.expected(self.matching_file(args, golden.as_ref()))
+    }
+
+    /// Identical to [`Executor::run_matching_file`], other than returning
+    /// an error in place of panicking.
+    ///
+    /// Failures to read or update the golden file are reported as
+    /// [`ExecutionError::Failed`].
+    pub fn try_run_matching_file<I, S, P>(
+        &self,
+        args: I,
+        golden: P,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        P: AsRef<Path>,
+    {
+        Unmet::into_result(self.matching_file(args, golden.as_ref()))
+    }
+
+    fn matching_file<I, S>(&self, args: I, golden: &Path) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let failed = |message| Unmet::Error(ExecutionError::Failed { message });
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some_and(|v| !v.is_empty() && v != "0");
+        if update {
+            let exec = self.expecting(args, |_| None)?;
+            if !exec.dry_run {
+                std::fs::write(golden, &exec.output().stdout).map_err(|err| {
+                    failed(format!("Failed to update {}: {}", golden.display(), err))
+                })?;
+            }
+            return Ok(exec);
+        }
+        let expected = std::fs::read(golden).map_err(|err| {
+            failed(format!(
+                "Failed to read {}: {} (set UPDATE_GOLDEN=1 to create it)",
+                golden.display(),
+                err
+            ))
+        })?;
+        self.expecting(args, |exec| {
+            let stdout = &exec.output().stdout;
+            (*stdout != expected).then(|| {
+                let expected = String::from_utf8_lossy(&expected);
+                let actual = String::from_utf8_lossy(stdout);
+                let mut diff =
+                    diff::unified(&expected, &actual, &golden.display().to_string(), "stdout");
+                if diff.is_empty() {
+                    // The difference is only in line endings.
+                    diff = format!("-{:?}\n+{:?}\n", expected, actual);
+                }
+                format!(
+                    "Expected stdout to match {} (set UPDATE_GOLDEN=1 to update it):\n{}",
+                    golden.display(),
+                    diff
+                )
+            })
+        })
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], the command is
+    /// expected to have created `path`, which is relative to the working
+    /// directory of the command.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path` does not exist afterwards, listing the contents
+    /// of its parent directory, or under any of the conditions which cause
+    /// [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_expecting_file<I, S, P>(&self, args: I, path: P) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        P: AsRef<Path>,
+    {
+        self.expected(self.expecting(args, |exec| {
+            let path = self.resolve(exec, path.as_ref());
+            missing_file(&path)
+        }))
+    }
+
+    /// Identical to [`Executor::run_expecting_file`], other than
+    /// returning an error in place of panicking.
+    pub fn try_run_expecting_file<I, S, P>(
+        &self,
+        args: I,
+        path: P,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        P: AsRef<Path>,
+    {
+        Unmet::into_result(self.expecting(args, |exec| {
+            let path = self.resolve(exec, path.as_ref());
+            missing_file(&path)
+        }))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// As [`Executor::run_expecting_file`], additionally expecting the
+    /// contents of `path` to be `expected`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path` does not exist afterwards, or its contents differ
+    /// from `expected`, or under any of the conditions which cause
+    /// [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_expecting_file_contents<I, S, P, C>(
+        &self,
+        args: I,
+        path: P,
+        expected: C,
+    ) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        P: AsRef<Path>,
+        C: AsRef<[u8]>,
+    {
+        self.expected(self.expecting_file_contents(args, path.as_ref(), expected.as_ref()))
+    }
+
+    /// Identical to [`Executor::run_expecting_file_contents`], other than
+    /// returning an error in place of panicking.
+    pub fn try_run_expecting_file_contents<I, S, P, C>(
+        &self,
+        args: I,
+        path: P,
+        expected: C,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        P: AsRef<Path>,
+        C: AsRef<[u8]>,
+    {
+        Unmet::into_result(self.expecting_file_contents(args, path.as_ref(), expected.as_ref()))
+    }
+
+    fn expecting_file_contents<I, S>(
+        &self,
+        args: I,
+        path: &Path,
+        expected: &[u8],
+    ) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expecting(args, |exec| {
+            let path = self.resolve(exec, path);
+            if let Some(reason) = missing_file(&path) {
+                return Some(reason);
+            }
+            let actual = match std::fs::read(&path) {
+                Ok(actual) => actual,
+                Err(err) => return Some(format!("Failed to read {}: {}", path.display(), err)),
+            };
+            (actual != expected).then(|| {
+                let expected = String::from_utf8_lossy(expected);
+                let actual = String::from_utf8_lossy(&actual);
+                let mut diff =
+                    diff::unified(&expected, &actual, "expected", &path.display().to_string());
+                if diff.is_empty() {
+                    // The difference is only in line endings.
+                    diff = format!("-{:?}\n+{:?}\n", expected, actual);
+                }
+                format!("Unexpected contents of {}:\n{}", path.display(), diff)
+            })
+        })
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], stdout is
+    /// expected to match the regular expression `pattern` somewhere. Only
+    /// available with the `regex` feature.
+    ///
+    /// As usual, `^` and `$` match only at the start and end of stdout;
+    /// use the `(?m)` flag to match them at the start and end of each
+    /// line, and `\A`/`\z` to anchor the whole output.
+    ///
+    /// # Panics
+    ///
+    /// Panics before launching the command if `pattern` is invalid. Panics
+    /// if stdout does not match `pattern`, or under any of the conditions
+    /// which cause [`Executor::run`] to panic.
+    #[cfg(feature = "regex")]
+    #[track_caller]
+    pub fn run_expecting_stdout_matches<I, S>(&self, args: I, pattern: &str) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expecting_match(args, Stream::Stdout, pattern)
+    }
+
+    /// As [`Executor::run_expecting_stdout_matches`], matching stderr in
+    /// place of stdout.
+    #[cfg(feature = "regex")]
+    #[track_caller]
+    pub fn run_expecting_stderr_matches<I, S>(&self, args: I, pattern: &str) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expecting_match(args, Stream::Stderr, pattern)
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], stderr is
+    /// expected to contain `needle`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if stderr does not contain `needle`, or under any of the
+    /// conditions which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_expecting_stderr_contains<I, S>(&self, args: I, needle: &str) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expected(self.expecting_stderr_contains(args, needle))
+    }
+
+    /// Identical to [`Executor::run_expecting_stderr_contains`], other
+    /// than returning an error in place of panicking.
+    pub fn try_run_expecting_stderr_contains<I, S>(
+        &self,
+        args: I,
+        needle: &str,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Unmet::into_result(self.expecting_stderr_contains(args, needle))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], stderr is
+    /// expected to be empty. This is deliberately strict: any output at
+    /// all, including warnings or progress reports, is treated as a
+    /// failure, so it is unsuitable for tools which write to stderr
+    /// under normal operation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if anything is written to stderr, or under any of the
+    /// conditions which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_expecting_clean_stderr<I, S>(&self, args: I) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expected(self.expecting_clean_stderr(args))
+    }
+
+    /// Identical to [`Executor::run_expecting_clean_stderr`], other than
+    /// returning an error in place of panicking.
+    pub fn try_run_expecting_clean_stderr<I, S>(
+        &self,
+        args: I,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Unmet::into_result(self.expecting_clean_stderr(args))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], the output
+    /// is expected to satisfy `predicate`. Use [`Executor::run_checking`]
+    /// to explain why the output is unacceptable.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `predicate` returns false, or under any of the
+    /// conditions which cause [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_asserting<I, S, F>(&self, args: I, predicate: F) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        F: FnOnce(&RunOutcome) -> bool,
+    {
+        self.run_checking(args, satisfying(predicate))
+    }
+
+    /// Identical to [`Executor::run_asserting`], other than returning an
+    /// error in place of panicking.
+    pub fn try_run_asserting<I, S, F>(
+        &self,
+        args: I,
+        predicate: F,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        F: FnOnce(&RunOutcome) -> bool,
+    {
+        self.try_run_checking(args, satisfying(predicate))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the captured output.
+    ///
+    /// In addition to the checks made by [`Executor::run`], the output
+    /// is expected to pass `check`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `check` returns an error, which is included in the
+    /// message, or under any of the conditions which cause
+    /// [`Executor::run`] to panic.
+    #[track_caller]
+    pub fn run_checking<I, S, F, E>(&self, args: I, check: F) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        F: FnOnce(&RunOutcome) -> Result<(), E>,
+        E: fmt::Display,
+    {
+        self.expected(self.expecting_check(args, check))
+    }
+
+    /// Identical to [`Executor::run_checking`], other than returning an
+    /// error in place of panicking.
+    pub fn try_run_checking<I, S, F, E>(
+        &self,
+        args: I,
+        check: F,
+    ) -> Result<RunOutcome, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        F: FnOnce(&RunOutcome) -> Result<(), E>,
+        E: fmt::Display,
+    {
+        Unmet::into_result(self.expecting_check(args, check))
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// the wall-clock time the sub-process took to complete.
+    ///
+    /// Only the execution of the sub-process itself is timed.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`Executor::run`].
+    #[track_caller]
+    pub fn run_timed<I, S>(&self, args: I) -> Duration
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let exec = self.run_successfully(args);
+        exec.elapsed.unwrap()
+    }
+
+    /// Launches each command in sequence, awaiting the completion of
+    /// each before launching the next.
+    ///
+    /// Output of successful commands is discarded.
+    ///
+    /// # Panics
+    ///
+    /// Panics as soon as any command fails, under any of the conditions
+    /// which cause [`Executor::run`] to panic, or once the deadline set
+    /// with [`Executor::with_total_deadline`] is exceeded. The panic
+    /// message identifies the (zero-based) index of the failing command.
+    /// As no output is returned, failures panic even under
+    /// [`FailureStrategy::Return`].
+    #[track_caller]
+    pub fn run_all<I, C, S>(&self, commands: I)
+    where
+        I: IntoIterator<Item = C>,
+        C: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let started = Instant::now();
+        let exceeded = |deadline: &Duration| started.elapsed() >= *deadline;
+        for (index, args) in commands.into_iter().enumerate() {
+            if let Some(deadline) = self.total_deadline.filter(exceeded) {
+                fail!(
+                    self,
+                    "Exceeded total deadline of {:?} before command at index {}",
+                    deadline,
+                    index
+                );
+            }
+            let result = self
+                .prepare(args)
+                .map_err(|err| self.render(&err))
+                .and_then(|mut exec| {
+                    exec.deadline = self.total_deadline.map(|deadline| started + deadline);
+                    self.checked(exec)
+                });
+            if let Err(msg) = result {
+                match self.total_deadline.filter(exceeded) {
+                    Some(deadline) => fail!(
+                        self,
+                        "Exceeded total deadline of {:?} at command index {}:\n{}",
+                        deadline,
+                        index,
+                        msg
+                    ),
+                    None => fail!(self, "Command at index {} failed:\n{}", index, msg),
+                }
+            }
+        }
+    }
+
+    /// Launches each command in sequence, awaiting the completion of
+    /// each before launching the next, regardless of whether earlier
+    /// commands failed.
+    ///
+    /// Output of successful commands is discarded. Each command which
+    /// fails, under any of the conditions which cause [`Executor::run`]
+    /// to panic, is reported once all commands have run.
+    pub fn run_all_collect<I, C, S>(&self, commands: I) -> Result<(), Vec<ExecutionFailure>>
+    where
+        I: IntoIterator<Item = C>,
+        C: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let failures: Vec<ExecutionFailure> = commands
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, args)| {
+                let error = self.try_run(args).err()?;
+                Some(ExecutionFailure { index, error })
+            })
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+
+    /// Launches every command concurrently, each on its own thread, and
+    /// awaits the completion of all of them, returning their captured
+    /// output in the order of `commands`.
+    ///
+    /// Output is captured separately for each command, so concurrent
+    /// commands do not corrupt each other's diagnostics.
+    ///
+    /// # Panics
+    ///
+    /// Panics once all commands have completed if any failed, under any
+    /// of the conditions which cause [`Executor::run`] to panic. The panic
+    /// message describes every failure, identified by the (zero-based)
+    /// index of the failing command.
+    #[track_caller]
+    pub fn run_parallel<I, C, S>(&self, commands: I) -> Vec<RunOutcome>
+    where
+        I: IntoIterator<Item = C>,
+        C: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        K: Sync,
+        V: Sync,
+    {
+        let execs = commands
+            .into_iter()
+            .map(|args| self.prepare(args))
+            .collect::<Result<Vec<_>, _>>()
+            .or_fail(self);
+        let count = execs.len();
+        let results: Vec<Result<Execution, String>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = execs
+                .into_iter()
+                .map(|exec| scope.spawn(move || self.checked_output(exec)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        });
+        let failures: Vec<String> = results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| {
+                let msg = result.as_ref().err()?;
+                Some(format!("Command at index {} failed:\n{}", index, msg))
+            })
+            .collect();
+        if !failures.is_empty() {
+            fail!(
+                self,
+                "{} of {} commands failed:\n{}",
+                failures.len(),
+                count,
+                failures.join("\n\n")
+            );
+        }
+        results
+            .into_iter()
+            .flatten()
+            .map(Execution::into_outcome)
+            .collect()
+    }
+
+    /// Launches the command once for each of `env_sets`, in turn, and
+    /// awaits the completion of every launch, returning their captured
+    /// output in the order of `env_sets`.
+    ///
+    /// Each set of variables is provided as by [`Executor::run_with_env`],
+    /// for checking that a command succeeds under several configurations
+    /// (such as different values of `LANG`).
+    ///
+    /// # Panics
+    ///
+    /// Panics once every launch has completed if any failed, under any of
+    /// the conditions which cause [`Executor::run_with_env`] to panic. The
+    /// panic message describes every failure, identified by the
+    /// (zero-based) index and variables of the failing set.
+    #[track_caller]
+    pub fn run_matrix<I, S, M, E, EK, EV>(&self, args: I, env_sets: M) -> Vec<RunOutcome>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        M: IntoIterator<Item = E>,
+        E: IntoIterator<Item = (EK, EV)>,
+        EK: AsRef<OsStr>,
+        EV: AsRef<OsStr>,
+    {
+        let args: Vec<OsString> = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_os_string())
+            .collect();
+        let mut outcomes = Vec::new();
+        let mut failures = Vec::new();
+        let mut count = 0;
+        for (index, env) in env_sets.into_iter().enumerate() {
+            count += 1;
+            let exec = self.prepare_with_env(&args, env).or_fail(self);
+            let vars = command_line(exec.env.iter().map(|(key, value)| {
+                let mut var = key.clone();
+                var.push("=");
+                var.push(value);
+                var
+            }));
+            match self.checked_output(exec) {
+                Ok(exec) => outcomes.push(exec.into_outcome()),
+                Err(msg) => failures.push(format!(
+                    "Environment at index {} ({}) failed:\n{}",
+                    index, vars, msg
+                )),
+            }
+        }
+        if !failures.is_empty() {
+            fail!(
+                self,
+                "{} of {} environments failed:\n{}",
+                failures.len(),
+                count,
+                failures.join("\n\n")
+            );
+        }
+        outcomes
+    }
+
+    /// Launches the commands as a pipeline, with the stdout of each
+    /// connected to the stdin of the next, and awaits their completion,
+    /// returning the captured output of the last command.
+    ///
+    /// Stdin, if configured, is provided to the first command. The timeout,
+    /// if one is set, does not apply, and commands are not retried.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `commands` (or any command within it) is empty, if any
+    /// command fails to execute, or if any command returns a non-zero
+    /// exit code. Unlike a shell, this includes commands terminated by
+    /// `SIGPIPE` because a later command exited without reading all of
+    /// its input. The panic message identifies the (zero-based) index of
+    /// the first failing command. Under [`FailureStrategy::Return`], the
+    /// output of the last command is returned even if a command failed.
+    #[track_caller]
+    pub fn pipe<I, C, S>(&self, commands: I) -> RunOutcome
+    where
+        I: IntoIterator<Item = C>,
+        C: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut execs = commands
+            .into_iter()
+            .map(|args| self.prepare(args))
+            .collect::<Result<Vec<_>, _>>()
+            .or_fail(self);
+        if execs.is_empty() {
+            self.fail(ExecutionError::EmptyArgs);
+        }
+        if self.dry_run {
+            let stages: Vec<String> = execs.iter().map(|exec| self.describe(exec)).collect();
+            eprintln!("[dry run] {}", stages.join(" | "));
+            return RunOutcome {
+                status: ExitStatus::default(),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+                pid: None,
+                peak_rss: None,
+                stdout_len: 0,
+                stderr_len: 0,
+                stdout_truncated: false,
+                stderr_truncated: false,
+                raw_stdout: None,
+                raw_stderr: None,
+            };
+        }
+        for exec in &execs {
+            self.before_launch(exec);
+        }
+        let commands = execs.iter_mut().map(|exec| self.command(exec)).collect();
+        let started = Instant::now();
+        let results = match spawn::pipeline(commands, &self.spawn_config()) {
+            Ok(results) => results,
+            Err((index, err)) => match execs[index].complete(self, Err(err)) {
+                Err(err) => self.fail(err),
+                Ok(_) => unreachable!("a failed launch completed"),
+            },
+        };
+        // The stages run concurrently, so each is timed as the pipeline.
+        let elapsed = started.elapsed();
+        for (index, (exec, captured)) in execs.iter_mut().zip(results).enumerate() {
+            exec.elapsed = Some(elapsed);
+            exec.complete(self, Ok(spawn::Waited::Completed(captured)))
+                .or_fail(self);
+            let returning = matches!(self.failure_strategy, FailureStrategy::Return);
+            if !exec.succeeded() && !returning {
+                fail!(self, "Pipeline stage {} failed:\n{}", index, exec);
+            }
+        }
+        execs.pop().unwrap().into_outcome()
+    }
+
+    /// Launches a new subprocess and awaits its completion, returning
+    /// each line of its stdout and stderr (without line endings), tagged
+    /// with the stream to which it was written.
+    ///
+    /// Both streams are read concurrently, and lines are recorded in the
+    /// order in which they are read. The order of lines within each
+    /// stream is preserved, but the order of lines across streams only
+    /// approximates the order in which they were written: the process
+    /// may buffer each stream differently, and lines written at nearly
+    /// the same time may be read in either order. Lines which are not
+    /// valid UTF-8 are decoded lossily.
+    ///
+    /// Captured output is not echoed, even with
+    /// [`Executor::with_streaming`].
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`Executor::run`].
+    #[track_caller]
+    pub fn run_tagged<I, S>(&self, args: I) -> Vec<(Stream, String)>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut exec = self.prepare(args).or_fail(self);
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        exec.tagged = Some(lines.clone());
+        exec.execute_until_success(self).or_fail(self);
+        self.succeeded(exec);
+        let mut lines = lines.lock().unwrap();
+        std::mem::take(&mut *lines)
+    }
+
+    /// Launches a new subprocess in the background, returning a handle
+    /// which may be used to await or kill it.
+    ///
+    /// Output is captured until the process is awaited. The timeout,
+    /// if one is set, does not apply.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `args` is empty, or if the sub-process fails to execute.
+    #[track_caller]
+    pub fn spawn<I, S>(&self, args: I) -> RunningProcess
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut exec = self.prepare(args).or_fail(self);
+        exec.color = self.color;
+        exec.normalize_newlines = self.normalize_newlines;
+        self.before_launch(&exec);
+        let spawned = spawn::spawn(&mut self.command(&mut exec), &self.spawn_config())
+            .map_err(|err| self.spawn_error(&exec.cmd, err))
+            .or_fail(self);
+        RunningProcess::new(exec, spawned, self.context.clone())
+    }
+
+    /// Launches a new subprocess in the background, as
+    /// [`Executor::spawn`], and waits until it accepts TCP connections on
+    /// `port` of `127.0.0.1`, for tests of servers.
+    ///
+    /// The port is polled until a connection succeeds, so there is no
+    /// need to sleep for a fixed time while the server starts. The test
+    /// connection is closed immediately.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the captured output if the process exits before the
+    /// port accepts connections, or if it does not do so within
+    /// `timeout` (in which case the process is killed), or under the
+    /// conditions which cause [`Executor::spawn`] to panic.
+    #[track_caller]
+    pub fn spawn_and_wait_for_port<I, S>(
+        &self,
+        args: I,
+        port: u16,
+        timeout: Duration,
+    ) -> RunningProcess
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut process = self.spawn(args);
+        let address = SocketAddr::from(([127, 0, 0, 1], port));
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                process.fail(
+                    format_args!("Port {} did not accept connections", port),
+                    timeout,
+                );
+            }
+            if TcpStream::connect_timeout(&address, remaining).is_ok() {
+                return process;
+            }
+            if process.wait_timeout(Duration::ZERO).is_some() {
+                process.fail(
+                    format_args!("Process exited before port {} accepted connections", port),
+                    timeout,
+                );
+            }
+            std::thread::sleep(PORT_POLL_INTERVAL.min(remaining));
+        }
+    }
+
+    /// Builds a `Command` for `args`, configured with the environment,
+    /// working directory and other process attributes of the executor,
+    /// for callers which need to customize it further.
+    ///
+    /// Configuration which applies to running the process, rather than
+    /// launching it (stdin, output redirection, timeouts, retries, and
+    /// hooks), is not applied.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `args` is empty.
+    #[track_caller]
+    pub fn build_command<I, S>(&self, args: I) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut exec = self.prepare(args).or_fail(self);
+        self.command(&mut exec)
+    }
+
+    /// Resolves `program` to the executable which would be launched to
+    /// run it, without launching it.
+    ///
+    /// A `program` containing a `/` is resolved against the working
+    /// directory of launched processes; any other is searched for in
+    /// the `PATH` which launched processes would observe. Returns `None`
+    /// if no executable file is found, such as for a program which is
+    /// not installed.
+    pub fn which(&self, program: &str) -> Option<PathBuf> {
+        let exec = Execution::new([program]).ok()?;
+        let current_dir = self.current_dir.as_deref().unwrap_or_else(|| Path::new(""));
+        if program.contains('/') {
+            let path = current_dir.join(program);
+            return is_executable(&path).then_some(path);
+        }
+        let path = self.visible_env(&exec, OsStr::new("PATH"))?;
+        std::env::split_paths(&path)
+            .map(|dir| current_dir.join(dir).join(program))
+            .find(|path| is_executable(path))
+    }
+
+    /// Launches the command, awaits its completion, and panics unless it
+    /// succeeded.
+    #[track_caller]
+    fn run_successfully<I, S>(&self, args: I) -> Execution
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut exec = self.prepare(args).or_fail(self);
+        exec.execute_until_success(self).or_fail(self);
+        self.succeeded(exec)
+    }
+
+    /// Returns the completed execution if it succeeded, and otherwise
+    /// handles its failure according to the failure strategy.
+    #[track_caller]
+    fn succeeded(&self, exec: Execution) -> Execution {
+        if exec.succeeded() {
+            return exec;
+        }
+        if let FailureStrategy::Return = self.failure_strategy {
+            return exec;
+        }
+        let msg = failure_message(&exec);
+        self.fail_with(exec.into_failure(), format_args!("{}", msg))
+    }
+
+    /// Launches `a` and `b`, expecting them to succeed and to write the
+    /// same stdout, once normalized.
+    #[track_caller]
+    fn same_stdout<I, S>(
+        &self,
+        a: I,
+        b: I,
+        normalize: Option<&dyn Fn(&str) -> String>,
+    ) -> (RunOutcome, RunOutcome)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let a = self.run_successfully(a);
+        let b = self.run_successfully(b);
+        if a.dry_run || !a.succeeded() || !b.succeeded() {
+            return (a.into_outcome(), b.into_outcome());
+        }
+        let (a_stdout, b_stdout) = (&a.output().stdout, &b.output().stdout);
+        if let Some(msg) = stdout_difference(&a.argv(), a_stdout, &b.argv(), b_stdout, normalize) {
+            if !matches!(self.failure_strategy, FailureStrategy::Return) {
+                fail!(self, "{}", msg);
+            }
+        }
+        (a.into_outcome(), b.into_outcome())
+    }
+
+    /// As [`Executor::same_stdout`], returning an error in place of
+    /// panicking.
+    fn try_same_stdout<I, S>(
+        &self,
+        a: I,
+        b: I,
+        normalize: Option<&dyn Fn(&str) -> String>,
+    ) -> Result<(RunOutcome, RunOutcome), ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let a: Vec<OsString> = a
+            .into_iter()
+            .map(|arg| arg.as_ref().to_os_string())
+            .collect();
+        let b: Vec<OsString> = b
+            .into_iter()
+            .map(|arg| arg.as_ref().to_os_string())
+            .collect();
+        let (a_output, b_output) = (self.try_run(&a)?, self.try_run(&b)?);
+        if !self.dry_run {
+            let difference =
+                stdout_difference(&a, &a_output.stdout, &b, &b_output.stdout, normalize);
+            if let Some(message) = difference {
+                return Err(ExecutionError::Failed { message });
+            }
+        }
+        Ok((a_output, b_output))
+    }
+
+    /// Launches `args` twice, expecting both runs to succeed, and the
+    /// second to write `expected` to stdout, if set.
+    #[track_caller]
+    fn idempotent<I, S>(&self, args: I, expected: Option<&[u8]>) -> (RunOutcome, RunOutcome)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<OsString> = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_os_string())
+            .collect();
+        let mut outcomes = Vec::new();
+        for run in ["First", "Second"] {
+            let exec = self.prepare(&args).or_fail(self);
+            let exec = match self.checked_output(exec) {
+                Ok(exec) => exec,
+                Err(msg) => fail!(self, "{} run failed:\n{}", run, msg),
+            };
+            outcomes.push(exec);
+        }
+        let second = outcomes.pop().unwrap();
+        let first = outcomes.pop().unwrap();
+        if let Some(expected) = expected {
+            let stdout = &second.output().stdout;
+            let returning = matches!(self.failure_strategy, FailureStrategy::Return);
+            if !second.dry_run && second.succeeded() && stdout != expected && !returning {
+                fail!(
+                    self,
+                    "Expected the second run to write {:?} to stdout, but got {:?}\n{}",
+                    String::from_utf8_lossy(expected),
+                    String::from_utf8_lossy(stdout),
+                    second
+                );
+            }
+        }
+        (first.into_outcome(), second.into_outcome())
+    }
+
+    /// As [`Executor::idempotent`], returning an error in place of
+    /// panicking.
+    fn try_idempotent<I, S>(
+        &self,
+        args: I,
+        expected: Option<&[u8]>,
+    ) -> Result<(RunOutcome, RunOutcome), ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<OsString> = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_os_string())
+            .collect();
+        let first = self.try_run(&args)?;
+        let second = Unmet::into_result(self.expecting(&args, |exec| {
+            let stdout = &exec.output().stdout;
+            let expected = expected.filter(|expected| stdout != expected)?;
+            Some(format!(
+                "Expected the second run to write {:?} to stdout, but got {:?}",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(stdout)
+            ))
+        }))?;
+        Ok((first, second))
+    }
+
+    /// Launches the execution and awaits its completion, returning the
+    /// pretty-printed failure unless it succeeded.
+    ///
+    /// For methods which have no output to return, so fail regardless of
+    /// the failure strategy.
+    fn checked(&self, mut exec: Execution) -> Result<Execution, String> {
+        exec.execute_until_success(self)
+            .map_err(|err| self.render(&err))?;
+        if !exec.succeeded() {
+            return Err(failure_message(&exec));
+        }
+        Ok(exec)
+    }
+
+    /// As [`Executor::checked`], but returning a failed execution under
+    /// [`FailureStrategy::Return`], for methods which return its output.
+    fn checked_output(&self, mut exec: Execution) -> Result<Execution, String> {
+        exec.execute_until_success(self)
+            .map_err(|err| self.render(&err))?;
+        if !exec.succeeded() {
+            if let FailureStrategy::Return = self.failure_strategy {
+                return Ok(exec);
+            }
+            return Err(failure_message(&exec));
+        }
+        Ok(exec)
+    }
+
+    /// Returns the output if the expectations about the execution were
+    /// met, and otherwise handles the failure according to the failure
+    /// strategy.
+    #[track_caller]
+    fn expected(&self, result: Result<Execution, Unmet>) -> RunOutcome {
+        match (result, &self.failure_strategy) {
+            (Ok(exec), _) => exec.into_outcome(),
+            (Err(Unmet::Failed(exec, _)), FailureStrategy::Return) => exec.into_outcome(),
+            (Err(unmet), _) => self.unmet(unmet),
+        }
+    }
+
+    /// Panics, describing the unmet expectation.
+    #[track_caller]
+    fn unmet(&self, unmet: Unmet) -> ! {
+        let msg = match &unmet {
+            Unmet::Error(err) => self.render(err),
+            Unmet::Failed(exec, None) => failure_message(exec),
+            Unmet::Failed(exec, Some(reason)) => format!("{}\n{}", reason, exec),
+        };
+        self.fail_with(unmet.into_error(), format_args!("{}", msg))
+    }
+
+    /// Launches the command and awaits its completion, requiring it to
+    /// succeed, and then to pass `check`, which describes the output if
+    /// it is unacceptable.
+    ///
+    /// The output of a dry run is not checked.
+    fn expecting<I, S, F>(&self, args: I, check: F) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        F: FnOnce(&Execution) -> Option<String>,
+    {
+        let mut exec = self.prepare(args).map_err(Unmet::Error)?;
+        exec.execute_until_success(self).map_err(Unmet::Error)?;
+        if !exec.succeeded() {
+            return Err(Unmet::Failed(Box::new(exec), None));
+        }
+        if exec.dry_run {
+            return Ok(exec);
+        }
+        match check(&exec) {
+            Some(reason) => Err(Unmet::Failed(Box::new(exec), Some(reason))),
+            None => Ok(exec),
+        }
+    }
+
+    fn expecting_status<I, S>(&self, args: I, expectation: &Expectation) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        if let Expectation::Success = expectation {
+            return self.expecting(args, |_| None);
+        }
+        let mut exec = self.prepare(args).map_err(Unmet::Error)?;
+        let status = exec.execute(self).map_err(Unmet::Error)?.status;
+        if exec.dry_run {
+            return Ok(exec);
+        }
+        let timed_out = exec.timed_out.is_some();
+        if let Expectation::Timeout = expectation {
+            if timed_out {
+                return Ok(exec);
+            }
+        } else if timed_out {
+            return Err(Unmet::Failed(Box::new(exec), None));
+        }
+        let reason = match (expectation, status.code()) {
+            (Expectation::Failure, _) if !status.success() => return Ok(exec),
+            (Expectation::Failure, _) => "Expected failure, but the command succeeded".to_string(),
+            (Expectation::Code(code), Some(actual)) if actual == *code => return Ok(exec),
+            (Expectation::Codes(allowed), Some(actual)) if allowed.contains(&actual) => {
+                return Ok(exec)
+            }
+            (Expectation::Code(_), Some(actual)) | (Expectation::Codes(_), Some(actual)) => {
+                format!("Expected {}, but got {}", expectation, actual)
+            }
+            (Expectation::Signal(signal), None) if termination_signal(&status) == Some(*signal) => {
+                return Ok(exec)
+            }
+            (_, Some(actual)) => {
+                format!("Expected {}, but exited with code {}", expectation, actual)
+            }
+            (_, None) => format!(
+                "Expected {}, but {}",
+                expectation,
+                describe_termination(&status)
+            ),
+        };
+        Err(Unmet::Failed(Box::new(exec), Some(reason)))
+    }
+
+    fn expecting_stdout_contains<I, S>(&self, args: I, needle: &str) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expecting(args, |exec| {
+            (!contains(&exec.output().stdout, needle.as_bytes()))
+                .then(|| format!("Expected stdout to contain {:?}", needle))
+        })
+    }
+
+    /// Launches `args`, expecting `stream` to match `pattern`.
+    #[cfg(feature = "regex")]
+    #[track_caller]
+    fn expecting_match<I, S>(&self, args: I, stream: Stream, pattern: &str) -> RunOutcome
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let regex = match regex::bytes::Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(err) => fail!(self, "Invalid pattern {:?}: {}", pattern, err),
+        };
+        self.expected(self.expecting(args, |exec| {
+            let output = exec.output();
+            let captured = match stream {
+                Stream::Stdout => &output.stdout,
+                Stream::Stderr => &output.stderr,
+            };
+            (!regex.is_match(captured))
+                .then(|| format!("Expected {} to match /{}/", stream, pattern))
+        }))
+    }
+
+    fn expecting_stdout_eq<I, S>(&self, args: I, expected: &[u8]) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expecting(args, |exec| {
+            let stdout = &exec.output().stdout;
+            (stdout != expected).then(|| {
+                format!(
+                    "Expected stdout to equal {:?}, but got {:?}",
+                    String::from_utf8_lossy(expected),
+                    String::from_utf8_lossy(stdout),
+                )
+            })
+        })
+    }
+
+    fn expecting_stdout_lines<I, S, L, T>(&self, args: I, expected: L) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        L: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.expecting(args, |exec| {
+            line_mismatch(&exec.output().stdout, expected)
+                .map(|mismatch| format!("Expected stdout {}", mismatch))
+        })
+    }
+
+    fn expecting_line_count<I, S>(&self, args: I, expected: usize) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expecting(args, |exec| {
+            let actual = String::from_utf8_lossy(&exec.output().stdout)
+                .lines()
+                .count();
+            (actual != expected)
+                .then(|| format!("Expected {} lines of stdout, but got {}", expected, actual))
+        })
+    }
+
+    fn expecting_stderr_contains<I, S>(&self, args: I, needle: &str) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expecting(args, |exec| {
+            (!contains(&exec.output().stderr, needle.as_bytes()))
+                .then(|| format!("Expected stderr to contain {:?}", needle))
+        })
+    }
+
+    fn expecting_clean_stderr<I, S>(&self, args: I) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.expecting(args, |exec| {
+            (exec.stderr_len > 0).then(|| "Expected stderr to be empty".to_string())
+        })
+    }
+
+    fn expecting_check<I, S, F, E>(&self, args: I, check: F) -> Result<Execution, Unmet>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        F: FnOnce(&RunOutcome) -> Result<(), E>,
+        E: fmt::Display,
+    {
+        self.expecting(args, |exec| {
+            let outcome = RunOutcome::from(exec.output().clone());
+            check(&outcome)
+                .err()
+                .map(|err| format!("Output failed check: {}", err))
+        })
+    }
+
+    /// Prepares an execution of `args`, interpolating variables into
+    /// them if enabled.
+    fn prepare<I, S>(&self, args: I) -> Result<Execution, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut exec = Execution::new(args)?;
+        self.interpolate(&mut exec)?;
+        self.canonicalize_path_args(&mut exec);
+        Ok(exec)
+    }
+
+    /// As [`Executor::prepare`], additionally providing `extra_env` to the
+    /// execution.
+    #[track_caller]
+    fn prepare_with_env<I, S, EI, EK, EV>(
+        &self,
+        args: I,
+        extra_env: EI,
+    ) -> Result<Execution, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        EI: IntoIterator<Item = (EK, EV)>,
+        EK: AsRef<OsStr>,
+        EV: AsRef<OsStr>,
+    {
+        let mut exec = Execution::new(args)?;
+        exec.env = extra_env
+            .into_iter()
+            .map(|(key, value)| {
+                validate_key(key.as_ref());
+                (key.as_ref().to_os_string(), value.as_ref().to_os_string())
+            })
+            .collect();
+        self.interpolate(&mut exec)?;
+        self.canonicalize_path_args(&mut exec);
+        Ok(exec)
+    }
+
+    /// Makes arguments of `exec` which are existing relative paths
+    /// absolute, if enabled with
+    /// [`Executor::with_path_arg_canonicalization`].
+    fn canonicalize_path_args(&self, exec: &mut Execution) {
+        if !self.canonicalize_path_args {
+            return;
+        }
+        for arg in &mut exec.args {
+            if !Path::new(arg).is_relative() {
+                continue;
+            }
+            if let Ok(path) = Path::new(arg).canonicalize() {
+                *arg = path.into_os_string();
+            }
+        }
+    }
+
+    /// Resolves `path` relative to the working directory of `exec`.
+    fn resolve(&self, exec: &Execution, path: &Path) -> PathBuf {
+        match exec.current_dir.as_ref().or(self.current_dir.as_ref()) {
+            Some(dir) => dir.join(path),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// Expands references to variables in the arguments of `exec`, if
+    /// enabled with [`Executor::with_arg_interpolation`].
+    fn interpolate(&self, exec: &mut Execution) -> Result<(), ExecutionError> {
+        if !self.interpolate_args {
+            return Ok(());
+        }
+        let mut argv = Vec::with_capacity(exec.args.len() + 1);
+        for arg in exec.argv() {
+            let arg = match arg.to_str() {
+                Some(arg) => shell::interpolate(arg, |name| {
+                    match self.visible_env(exec, OsStr::new(name)) {
+                        Some(value) => Ok(value),
+                        None if self.empty_undefined_vars => Ok(OsString::new()),
+                        None => Err(ExecutionError::UndefinedVariable {
+                            name: name.to_string(),
+                            arg: arg.to_string(),
+                        }),
+                    }
+                })?,
+                // Arguments which are not unicode are passed unmodified.
+                None => arg,
+            };
+            argv.push(arg);
+        }
+        let mut argv = argv.into_iter();
+        exec.cmd = argv.next().unwrap();
+        exec.args = argv.collect();
+        Ok(())
+    }
+
+    /// Returns the value of the variable `key` in the environment of a
+    /// process launched for `exec`, if set.
+    fn visible_env(&self, exec: &Execution, key: &OsStr) -> Option<OsString> {
+        let env = self.assembled_env(exec);
+        if let Some((_, value)) = env.into_iter().rev().find(|(k, _)| k == key) {
+            return Some(value);
+        }
+        self.inherited_env(key)
+    }
+
+    /// The value of `key` in the caller's environment, if launched
+    /// processes inherit it.
+    fn inherited_env(&self, key: &OsStr) -> Option<OsString> {
+        if self.removed_env.iter().any(|k| k == key) {
+            return None;
+        }
+        if self.clear_env && !self.kept_env.iter().any(|k| k == key) {
+            return None;
+        }
+        std::env::var_os(key)
+    }
+
+    /// The variables inherited from the caller despite `clear_env`.
+    fn kept_env(&self) -> impl Iterator<Item = (&OsStr, OsString)> + '_ {
+        self.kept_env
+            .iter()
+            .filter_map(move |key| Some((key.as_os_str(), self.inherited_env(key)?)))
+    }
+
+    /// Pretty-prints the error.
+    fn render(&self, err: &ExecutionError) -> String {
+        err.styled(self.color.enabled()).to_string()
+    }
+
+    /// Panics, pretty-printing the error.
+    #[track_caller]
+    fn fail(&self, err: ExecutionError) -> ! {
+        let msg = self.render(&err);
+        self.fail_with(err, format_args!("{}", msg))
+    }
+
+    /// Panics with `msg`, prefixed by the executor's context label.
+    #[track_caller]
+    fn panic(&self, msg: fmt::Arguments) -> ! {
+        let err = ExecutionError::Failed {
+            message: msg.to_string(),
+        };
+        self.fail_with(err, msg)
+    }
+
+    /// Reports `err` to the handler of the failure strategy, if any, and
+    /// then panics with `msg`, prefixed by the executor's context label.
+    #[track_caller]
+    fn fail_with(&self, err: ExecutionError, msg: fmt::Arguments) -> ! {
+        if let FailureStrategy::Custom(handler) = &self.failure_strategy {
+            handler(err);
+        }
+        if let Some((var, seed)) = &self.seed {
+            let msg = msg.to_string();
+            let separator = if msg.ends_with('\n') { "" } else { "\n" };
+            panic_in_context(
+                self.context.as_deref(),
+                format_args!(
+                    "{}{}Seed: {}={}",
+                    msg,
+                    separator,
+                    var.to_string_lossy(),
+                    seed
+                ),
+            )
+        }
+        panic_in_context(self.context.as_deref(), msg)
+    }
+
+    /// Builds a `Command` for the execution, configured by the executor.
+    ///
+    /// The variables are assembled once, as those computed lazily may
+    /// differ between calls, and recorded in `exec` for the transcript.
+    fn command(&self, exec: &mut Execution) -> Command {
+        let env = self.assembled_env(exec);
+        #[cfg(unix)]
+        let mut command = if self.sudo {
+            let mut command = Command::new("sudo");
+            command.args(sudo_args(&env)).arg(&exec.cmd);
+            command
+        } else {
+            Command::new(&exec.cmd)
+        };
+        #[cfg(not(unix))]
+        let mut command = Command::new(&exec.cmd);
+        if self.clear_env {
+            command.env_clear().envs(self.kept_env());
+        }
+        for key in &self.removed_env {
+            command.env_remove(key);
+        }
+        command.args(&exec.args).envs(env.iter().cloned());
+        let current_dir = exec.current_dir.as_ref().or(self.current_dir.as_ref());
+        #[cfg(unix)]
+        let current_dir = current_dir.filter(|_| self.chroot.is_none());
+        if let Some(dir) = current_dir {
+            command.current_dir(dir);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            if self.new_process_group {
+                command.process_group(0);
+            }
+            if let Some(uid) = self.uid {
+                command.uid(uid);
+            }
+            if let Some(gid) = self.gid {
+                command.gid(gid);
+            }
+            if let Some(mode) = self.umask {
+                // SAFETY: `umask` is async-signal-safe, and cannot fail.
+                unsafe {
+                    command.pre_exec(move || {
+                        libc::umask(mode as libc::mode_t);
+                        Ok(())
+                    });
+                }
+            }
+            for &(fd, child_fd) in &self.inherited_fds {
+                // SAFETY: `inherit_fd` only calls `dup2` and `fcntl`, which
+                // are async-signal-safe, and only modifies the descriptor
+                // table of the child.
+                unsafe {
+                    command.pre_exec(move || inherit_fd(fd, child_fd));
+                }
+            }
+            for &(resource, soft, hard) in &self.rlimits {
+                // SAFETY: `Rlimit::set` only calls `setrlimit`, which is
+                // async-signal-safe.
+                unsafe {
+                    command.pre_exec(move || resource.set(soft, hard));
+                }
+            }
+            if let Some(root) = &self.chroot {
+                let dir = exec.current_dir.as_ref().or(self.current_dir.as_ref());
+                // Both are allocated before forking. A working directory
+                // with a NUL byte is replaced by "", so fails to be found.
+                let root = c_path(root).unwrap_or_default();
+                let dir = c_path(dir.map_or(Path::new("/"), PathBuf::as_path)).unwrap_or_default();
+                // SAFETY: `chroot` and `chdir` are async-signal-safe, and
+                // the paths are owned by the closure.
+                unsafe {
+                    command.pre_exec(move || {
+                        if libc::chroot(root.as_ptr()) != 0 || libc::chdir(dir.as_ptr()) != 0 {
+                            return Err(io::Error::last_os_error());
+                        }
+                        Ok(())
+                    });
+                }
+            }
+            for f in &self.pre_exec {
+                let f = f.clone();
+                // SAFETY: The caller of `with_pre_exec` guarantees that `f`
+                // may be called before `exec`. The mutex is only locked in
+                // children, so is never held when the caller forks, and
+                // locking it cannot block.
+                unsafe {
+                    command.pre_exec(move || {
+                        let mut f = f.lock().unwrap_or_else(|err| err.into_inner());
+                        (*f)()
+                    });
+                }
+            }
+        }
+        #[cfg(feature = "serde")]
+        {
+            exec.launched_env = env;
+        }
+        command
+    }
+
+    /// Describes an error launching `program`, adding context where the
+    /// cause is likely to be the executor's configuration.
+    fn spawn_error(&self, program: &OsStr, err: io::Error) -> ExecutionError {
+        #[cfg(unix)]
+        let err = if err.kind() == io::ErrorKind::PermissionDenied
+            && (self.uid.is_some() || self.gid.is_some())
+        {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "{} (changing the uid/gid of a process typically requires root)",
+                    err
+                ),
+            )
+        } else {
+            err
+        };
+        #[cfg(unix)]
+        let err = match (&self.chroot, err.kind()) {
+            (Some(_), io::ErrorKind::PermissionDenied) => io::Error::new(
+                err.kind(),
+                format!(
+                    "{} (changing the root directory typically requires root)",
+                    err
+                ),
+            ),
+            (Some(root), io::ErrorKind::NotFound) => io::Error::new(
+                err.kind(),
+                format!(
+                    "{} (the program must exist within the root directory {})",
+                    err,
+                    root.display()
+                ),
+            ),
+            _ => err,
+        };
+        ExecutionError::SpawnFailed {
+            program: program.to_os_string(),
+            source: err,
+        }
+    }
+
+    /// The variables configured for `exec`, in the order they are applied.
+    fn assembled_env(&self, exec: &Execution) -> Vec<(OsString, OsString)> {
+        let env = self
+            .env
+            .iter()
+            .map(|(k, v)| (k.as_ref(), v.as_ref()))
+            .filter(|(k, _)| self.locale.is_none() || !LOCALE_VARS.iter().any(|var| k == var));
+        let path = self
+            .path
+            .iter()
+            .map(|path| (OsStr::new("PATH"), path.as_os_str()));
+        let locale = self.locale.iter().flat_map(|locale| {
+            [
+                (OsStr::new("LC_ALL"), locale.as_os_str()),
+                (OsStr::new("LANG"), locale.as_os_str()),
+            ]
+        });
+        let extra_env = exec.env.iter().map(|(k, v)| (k.as_os_str(), v.as_os_str()));
+        let mut assembled: Vec<(OsString, OsString)> = env
+            .map(|(k, v)| (k.to_os_string(), v.to_os_string()))
+            .collect();
+        assembled.extend(self.lazy_env.iter().map(|f| {
+            let (key, value) = f();
+            validate_key(&key);
+            (key, value)
+        }));
+        assembled.extend(
+            path.chain(locale)
+                .map(|(k, v)| (k.to_os_string(), v.to_os_string())),
+        );
+        assembled.extend(
+            self.seed
+                .iter()
+                .map(|(var, seed)| (var.clone(), seed.to_string().into())),
+        );
+        assembled.extend(extra_env.map(|(k, v)| (k.to_os_string(), v.to_os_string())));
+        if let Some(env_fn) = &self.env_fn {
+            env_fn(&mut assembled);
+        }
+        assembled
+    }
+
+    /// Invokes the pre-run hook, if any, for the execution.
+    fn before_launch(&self, exec: &Execution) {
+        #[cfg(feature = "tracing")]
+        trace::launching(exec);
+        if self.verbose {
+            let line = command_line(exec.argv());
+            let color = self.color.enabled();
+            eprintln!("+ {}", color::painted(color, color::COMMAND, &line));
+        }
+        if let Some(hook) = &self.pre_run_hook {
+            let argv: Vec<&OsStr> = std::iter::once(exec.cmd.as_os_str())
+                .chain(exec.args.iter().map(OsString::as_os_str))
+                .collect();
+            hook(&argv);
+        }
+    }
+
+    /// Describes how the execution would be launched, prefixing the
+    /// command with the configured working directory and environment.
+    fn describe(&self, exec: &Execution) -> String {
+        let mut prefix = String::new();
+        if let Some(dir) = exec.current_dir.as_ref().or(self.current_dir.as_ref()) {
+            let dir = dir.to_string_lossy();
+            prefix.push_str(&format!("cd {} && ", shell::quote(&dir)));
+        }
+        if self.clear_env {
+            prefix.push_str("env -i ");
+            for (key, value) in self.kept_env() {
+                let key = key.to_string_lossy();
+                let value = value.to_string_lossy();
+                prefix.push_str(&format!("{}={} ", key, shell::quote(&value)));
+            }
+        } else if !self.removed_env.is_empty() {
+            prefix.push_str("env ");
+            for key in &self.removed_env {
+                prefix.push_str(&format!("-u {} ", shell::quote(&key.to_string_lossy())));
+            }
+        }
+        let env = self.assembled_env(exec);
+        for (key, value) in &env {
+            let key = key.to_string_lossy();
+            let value = value.to_string_lossy();
+            prefix.push_str(&format!("{}={} ", key, shell::quote(&value)));
+        }
+        #[cfg(unix)]
+        if self.sudo {
+            prefix.push_str("sudo");
+            for arg in sudo_args(&env) {
+                prefix.push(' ');
+                prefix.push_str(&shell::quote(&arg.to_string_lossy()));
+            }
+            prefix.push(' ');
+        }
+        format!("{}{}", prefix, exec)
+    }
+
+    fn spawn_config(&self) -> spawn::Config<'_> {
+        spawn::Config {
+            stdin: self.stdin.as_deref(),
+            stdin_file: self.stdin_file.as_deref(),
+            timeout: self.timeout,
+            #[cfg(unix)]
+            timeout_kill: self.timeout_kill,
+            stdout: &self.stdout,
+            stderr: &self.stderr,
+            stdout_append: self.stdout_append,
+            stderr_append: self.stderr_append,
+            streaming: self.streaming,
+            max_output_bytes: self.max_output_bytes,
+            inherit_stdio: self.inherit_stdio,
+            merge_output: self.merge_output,
+            #[cfg(all(unix, feature = "pty"))]
+            pty: self.pty,
+            stdout_sink: self.stdout_sink.as_ref(),
+            tagged: None,
+        }
+    }
+}
+
+impl<K, V> Default for Executor<K, V>
+where
+    K: AsRef<OsStr> + Clone,
+    V: AsRef<OsStr> + Clone,
+{
+    /// Initializes a new Executor which provides no additional
+    /// environment variables.
+    fn default() -> Self {
+        Executor::new(Vec::new())
+    }
+}
+
+/// Shows the configured environment, with the values of variables
+/// redacted unless enabled by [`Executor::with_debug_show_values`].
+impl<K, V> fmt::Debug for Executor<K, V>
+where
+    K: AsRef<OsStr> + Clone,
+    V: AsRef<OsStr> + Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let env: Vec<EnvVar> = self
+            .env
+            .iter()
+            .map(|(key, value)| EnvVar {
+                key: key.as_ref(),
+                value: self.debug_show_values.then_some(value.as_ref()),
+            })
+            .collect();
+        f.debug_struct("Executor")
+            .field("env", &env)
+            .field("removed_env", &self.removed_env)
+            .field("clear_env", &self.clear_env)
+            .field("path", &self.path)
+            .field("current_dir", &self.current_dir)
+            .field("timeout", &self.timeout)
+            .field("retries", &self.retries)
+            .field("context", &self.context)
+            .field("dry_run", &self.dry_run)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The key of a variable provided by an [`Executor`], as given, or with
+/// the prefix set by [`Executor::with_env_prefix`] prepended.
+#[derive(Clone, Debug, PartialEq)]
+enum EnvKey<K> {
+    Given(K),
+    Prefixed(OsString),
+}
+
+impl<K: AsRef<OsStr>> AsRef<OsStr> for EnvKey<K> {
+    fn as_ref(&self) -> &OsStr {
+        match self {
+            EnvKey::Given(key) => key.as_ref(),
+            EnvKey::Prefixed(key) => key,
+        }
+    }
+}
+
+/// An environment variable, as debug-printed by an [`Executor`], whose
+/// value is redacted if unset.
+struct EnvVar<'a> {
+    key: &'a OsStr,
+    value: Option<&'a OsStr>,
+}
+
+impl fmt::Debug for EnvVar<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = self
+            .value
+            .map_or(Cow::Borrowed("***"), OsStr::to_string_lossy);
+        write!(f, "{}={}", self.key.to_string_lossy(), value)
+    }
+}
+
+impl StringExecutor {
+    /// Initializes a new Executor with a snapshot of the environment
+    /// variables of the current process.
+    ///
+    /// Further variables may be layered on top with [`Executor::with_env`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any environment variable is not valid unicode.
+    pub fn from_current_env() -> Self {
+        Executor::new(std::env::vars().collect())
+    }
+
+    /// Initializes a new Executor with the variables defined in the
+    /// dotenv file at `path`, such as the `.env` file of an application.
+    ///
+    /// Lines are either blank, comments beginning with `#`, or
+    /// assignments `KEY=VALUE`, optionally prefixed by `export`. Values
+    /// may be single-quoted (preserving their contents) or double-quoted
+    /// (supporting the escapes `\n`, `\"` and `\\`); unquoted values
+    /// end at a ` #` comment. Variables are not expanded within values.
+    ///
+    /// Only available with the `dotenv` feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file cannot be read, or if any line is malformed,
+    /// identifying the line.
+    #[cfg(feature = "dotenv")]
+    #[track_caller]
+    pub fn from_dotenv<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err));
+        let env = dotenv::parse(&contents)
+            .unwrap_or_else(|err| panic!("Failed to parse {}: {}", path.display(), err));
+        Executor::new(env)
+    }
+}
+
+/// A failure to meet the expectations of an assertion helper.
+enum Unmet {
+    /// The command could not be run.
+    Error(ExecutionError),
+    /// The command ran, but not as expected. The reason is unset if the
+    /// command simply failed.
+    Failed(Box<Execution>, Option<String>),
+}
+
+impl Unmet {
+    /// Converts the result of an assertion helper into that of its
+    /// non-panicking counterpart.
+    fn into_result(result: Result<Execution, Unmet>) -> Result<RunOutcome, ExecutionError> {
+        result
+            .map(Execution::into_outcome)
+            .map_err(Unmet::into_error)
+    }
+
+    /// Converts the failure into the error which a non-panicking
+    /// assertion helper returns.
+    fn into_error(self) -> ExecutionError {
+        match self {
+            Unmet::Error(err) => err,
+            Unmet::Failed(exec, None) => exec.into_failure(),
+            Unmet::Failed(exec, Some(reason)) => {
+                let command = exec.argv();
+                let output = exec.into_output();
+                ExecutionError::Unexpected {
+                    reason,
+                    command,
+                    status: output.status,
+                    stdout: output.stdout,
+                    stderr: output.stderr,
+                }
+            }
+        }
+    }
+}
+
+struct Execution {
+    cmd: OsString,
+    args: Vec<OsString>,
+    /// Variables provided in addition to (and overriding) those of the
+    /// executor.
+    env: Vec<(OsString, OsString)>,
+    /// The working directory, overriding that of the executor.
+    current_dir: Option<PathBuf>,
+    /// The timeout, overriding that of the executor.
+    timeout: Option<Duration>,
+    /// If set, every attempt must complete by this instant, as for
+    /// [`Executor::with_total_deadline`].
+    deadline: Option<Instant>,
+    result: Option<Output>,
+    elapsed: Option<Duration>,
+    /// Set if the command exceeded this timeout, and was killed.
+    timed_out: Option<Duration>,
+    /// The process identifier of the most recent launch.
+    pid: Option<u32>,
+    /// The peak resident set size of the most recent launch, in bytes.
+    peak_rss: Option<u64>,
+    /// Set if the command exited successfully, but its output was
+    /// rejected, describing why.
+    rejected: Option<String>,
+    /// The number of times the command has been launched.
+    attempts: usize,
+    color: ColorChoice,
+    /// The number of lines of each stream shown when displayed.
+    output_tail: Option<usize>,
+    /// Set if the command was printed, rather than launched.
+    dry_run: bool,
+    /// Where stdout/stderr were written.
+    stdout: StdioMode,
+    stderr: StdioMode,
+    /// Set if stdio was inherited, rather than captured.
+    inherited_stdio: bool,
+    /// The number of bytes written to stdout/stderr, which may exceed
+    /// the number captured.
+    stdout_len: usize,
+    stderr_len: usize,
+    /// Set if bytes written to stdout/stderr were discarded, beyond the
+    /// output limit.
+    stdout_truncated: bool,
+    stderr_truncated: bool,
+    /// Set if line endings in captured output are normalized, as for
+    /// [`Executor::with_normalize_newlines`].
+    normalize_newlines: bool,
+    /// The captured stdout/stderr before normalization, if it changed.
+    raw_stdout: Option<Vec<u8>>,
+    raw_stderr: Option<Vec<u8>>,
+    /// The variables provided to the most recent launch, as recorded in
+    /// the transcript.
+    #[cfg(feature = "serde")]
+    launched_env: Vec<(OsString, OsString)>,
+    /// If set, the lines of stdout and stderr of the most recent launch
+    /// are recorded here, as for [`Executor::run_tagged`].
+    tagged: Option<Arc<spawn::TaggedLines>>,
+}
+
+impl Execution {
+    fn new<I, S>(args: I) -> Result<Self, ExecutionError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut iter = args.into_iter().map(|arg| arg.as_ref().to_os_string());
+        Ok(Execution {
+            cmd: iter.next().ok_or(ExecutionError::EmptyArgs)?,
+            args: iter.collect(),
+            env: Vec::new(),
+            current_dir: None,
+            timeout: None,
+            deadline: None,
+            result: None,
+            elapsed: None,
+            timed_out: None,
+            pid: None,
+            peak_rss: None,
+            rejected: None,
+            attempts: 0,
+            color: ColorChoice::default(),
+            output_tail: None,
+            dry_run: false,
+            stdout: StdioMode::Capture,
+            stderr: StdioMode::Capture,
+            inherited_stdio: false,
+            tagged: None,
+            stdout_len: 0,
+            stderr_len: 0,
+            stdout_truncated: false,
+            stderr_truncated: false,
+            normalize_newlines: false,
+            raw_stdout: None,
+            raw_stderr: None,
+            #[cfg(feature = "serde")]
+            launched_env: Vec::new(),
+        })
+    }
+
+    /// The full command line, including the command itself.
+    fn argv(&self) -> Vec<OsString> {
+        let mut argv = vec![self.cmd.clone()];
+        argv.extend(self.args.iter().cloned());
+        argv
+    }
+
+    /// Launches the command and awaits its completion, regardless of
+    /// the exit status.
+    ///
+    /// Only fails if the command cannot be launched; timeouts are
+    /// recorded in `timed_out`.
+    fn execute<K, V>(&mut self, executor: &Executor<K, V>) -> Result<&Output, ExecutionError>
+    where
+        K: AsRef<OsStr> + Clone,
+        V: AsRef<OsStr> + Clone,
+    {
+        self.execute_with(executor, &|_| ())
+    }
+
+    /// As [`Execution::execute`], calling `configure` with the command
+    /// before it is launched.
+    fn execute_with<K, V>(
+        &mut self,
+        executor: &Executor<K, V>,
+        configure: &dyn Fn(&mut Command),
+    ) -> Result<&Output, ExecutionError>
+    where
+        K: AsRef<OsStr> + Clone,
+        V: AsRef<OsStr> + Clone,
+    {
+        if executor.dry_run {
+            return Ok(self.print(executor));
+        }
+        #[cfg(feature = "tracing")]
+        let span = trace::span(self, executor.context.as_deref());
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+        let _group = executor
+            .ci_grouping
+            .and_then(|flavor| ci::Group::start(flavor, &command_line(self.argv())));
+        executor.before_launch(self);
+        let mut command = executor.command(self);
+        configure(&mut command);
+        let started = Instant::now();
+        let mut config = executor.spawn_config();
+        config.timeout = self.timeout.or(config.timeout);
+        if let Some(lines) = &self.tagged {
+            // Only the lines of the final attempt are reported.
+            lines.lock().unwrap().clear();
+            config.tagged = Some(lines);
+        }
+        let waited = spawn::output(&mut command, &config);
+        self.elapsed = Some(started.elapsed());
+        self.complete(executor, waited)?;
+        #[cfg(feature = "tracing")]
+        trace::completed(&span, self);
+        Ok(self.output())
+    }
+
+    /// Launches the command until it succeeds, or the executor's retries
+    /// are exhausted.
+    fn execute_until_success<K, V>(
+        &mut self,
+        executor: &Executor<K, V>,
+    ) -> Result<(), ExecutionError>
+    where
+        K: AsRef<OsStr> + Clone,
+        V: AsRef<OsStr> + Clone,
+    {
+        self.execute_until_success_with(executor, &|_| ())
+    }
+
+    /// As [`Execution::execute_until_success`], calling `configure` with
+    /// the command before each launch.
+    fn execute_until_success_with<K, V>(
+        &mut self,
+        executor: &Executor<K, V>,
+        configure: &dyn Fn(&mut Command),
+    ) -> Result<(), ExecutionError>
+    where
+        K: AsRef<OsStr> + Clone,
+        V: AsRef<OsStr> + Clone,
+    {
+        let timeout = self.timeout.or(executor.timeout);
+        loop {
+            if let Some(deadline) = self.deadline {
+                // Each attempt is limited to the time remaining.
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                self.timeout = Some(timeout.map_or(remaining, |t| t.min(remaining)));
+            }
+            self.execute_with(executor, configure)?;
+            let expired = self
+                .deadline
+                .is_some_and(|deadline| Instant::now() + executor.retry_delay >= deadline);
+            if self.succeeded() || self.attempts > executor.retries || expired {
+                return Ok(());
+            }
+            std::thread::sleep(executor.retry_delay);
+        }
+    }
+
+    /// Prints the command, and records it as a success without output.
+    fn print<K, V>(&mut self, executor: &Executor<K, V>) -> &Output
+    where
+        K: AsRef<OsStr> + Clone,
+        V: AsRef<OsStr> + Clone,
+    {
+        self.color = executor.color;
+        self.dry_run = true;
+        eprintln!("[dry run] {}", executor.describe(self));
+        self.elapsed = Some(Duration::default());
+        self.result.insert(Output {
+            status: ExitStatus::default(),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        })
+    }
+
+    /// Records the result of awaiting the command, counting the launch
+    /// in the stats of `executor`, if accumulated, and appending it to
+    /// its transcript, if set.
+    ///
+    /// Every launch completes here, whether blocking, asynchronous or a
+    /// stage of a pipeline, and whether or not it could be spawned.
+    fn complete<K, V>(
+        &mut self,
+        executor: &Executor<K, V>,
+        waited: io::Result<spawn::Waited>,
+    ) -> Result<&Output, ExecutionError>
+    where
+        K: AsRef<OsStr> + Clone,
+        V: AsRef<OsStr> + Clone,
+    {
+        let completed = self.complete_attempt(executor, waited).map(drop);
+        #[cfg(feature = "serde")]
+        if let Some(transcript) = &executor.transcript {
+            let dir = self.current_dir.as_ref().or(executor.current_dir.as_ref());
+            transcript.record(self, dir, &completed, executor.debug_show_values);
+        }
+        if let Some(stats) = &executor.stats {
+            // A command which failed to spawn keeps the output of any
+            // earlier attempt, which must not be counted again.
+            let launched = completed.is_ok();
+            stats.lock().unwrap().record(
+                self.elapsed.unwrap_or_default(),
+                launched && self.succeeded(),
+                launched && self.timed_out.is_some(),
+            );
+        }
+        completed?;
+        Ok(self.output())
+    }
+
+    /// Records the result of awaiting the command, as
+    /// [`Execution::complete`], other than for the stats and transcript.
+    fn complete_attempt<K, V>(
+        &mut self,
+        executor: &Executor<K, V>,
+        waited: io::Result<spawn::Waited>,
+    ) -> Result<&Output, ExecutionError>
+    where
+        K: AsRef<OsStr> + Clone,
+        V: AsRef<OsStr> + Clone,
+    {
+        self.color = executor.color;
+        self.output_tail = executor.output_tail;
+        self.stdout = executor.stdout.clone();
+        self.stderr = executor.stderr.clone();
+        self.inherited_stdio = executor.inherit_stdio;
+        self.attempts += 1;
+        let waited = match waited {
+            Ok(waited) => waited,
+            Err(err) => {
+                let err = executor.spawn_error(&self.cmd, err);
+                #[cfg(feature = "tracing")]
+                trace::spawn_failed(self, &err);
+                return Err(err);
+            }
+        };
+        let mut captured = match waited {
+            spawn::Waited::Completed(captured) => {
+                self.timed_out = None;
+                captured
+            }
+            spawn::Waited::TimedOut(captured) => {
+                // A child only times out if a timeout was set.
+                self.timed_out = self.timeout.or(executor.timeout);
+                captured
+            }
+        };
+        self.stdout_len = captured.stdout_len;
+        self.stderr_len = captured.stderr_len;
+        self.stdout_truncated = captured.stdout_truncated;
+        self.stderr_truncated = captured.stderr_truncated;
+        self.normalize_newlines = executor.normalize_newlines;
+        self.raw_stdout = None;
+        self.raw_stderr = None;
+        if self.normalize_newlines {
+            self.raw_stdout = outcome::normalize_newlines(&mut captured.output.stdout);
+            self.raw_stderr = outcome::normalize_newlines(&mut captured.output.stderr);
+        }
+        self.pid = captured.pid;
+        self.peak_rss = captured.peak_rss;
+        if executor.verbose {
+            if let Some(pid) = self.pid {
+                eprintln!("+ [pid {}] {}", pid, captured.output.status);
+            }
+        }
+        self.rejected = None;
+        #[cfg(unix)]
+        if executor.sudo && captured.output.status.code() == Some(1) {
+            let stderr = String::from_utf8_lossy(&captured.output.stderr);
+            let prompted = stderr.lines().any(|line| {
+                line.starts_with("sudo: a password is required")
+                    || line.starts_with("sudo: a terminal is required")
+            });
+            if prompted {
+                self.rejected =
+                    Some("Command requires passwordless sudo, but a password is required".into());
+            }
+        }
+        if executor.strict_stderr && self.timed_out.is_none() && captured.output.status.success() {
+            let stderr = String::from_utf8_lossy(&captured.output.stderr);
+            let allowed = |line: &str| {
+                executor
+                    .stderr_allow
+                    .iter()
+                    .any(|p| line.contains(p.as_str()))
+            };
+            self.rejected = stderr
+                .lines()
+                .find(|line| !allowed(line))
+                .map(|line| format!("Expected no output to stderr, but got {:?}", line));
+        }
+        let output = self.result.insert(captured.output);
+        if let Some(hook) = &executor.post_run_hook {
+            if self.timed_out.is_none() && self.rejected.is_none() && output.status.success() {
+                hook(output);
+            }
+        }
+        Ok(output)
+    }
+
+    /// Launches the command, and expects it to succeed.
+    fn run<K, V>(mut self, executor: &Executor<K, V>) -> Result<RunOutcome, ExecutionError>
+    where
+        K: AsRef<OsStr> + Clone,
+        V: AsRef<OsStr> + Clone,
+    {
+        self.execute_until_success(executor)?;
+        self.into_success()
+    }
+
+    /// The output of the completed command.
+    fn output(&self) -> &Output {
+        self.result.as_ref().expect("Command has not run")
+    }
+
+    fn into_output(self) -> Output {
+        self.result.expect("Command has not run")
+    }
+
+    fn into_outcome(mut self) -> RunOutcome {
+        RunOutcome {
+            pid: self.pid,
+            peak_rss: self.peak_rss,
+            stdout_len: self.stdout_len,
+            stderr_len: self.stderr_len,
+            stdout_truncated: self.stdout_truncated,
+            stderr_truncated: self.stderr_truncated,
+            raw_stdout: self.raw_stdout.take(),
+            raw_stderr: self.raw_stderr.take(),
+            ..RunOutcome::from(self.into_output())
+        }
+    }
+
+    /// Returns true if the completed command exited successfully,
+    /// within the timeout.
+    fn succeeded(&self) -> bool {
+        self.timed_out.is_none() && self.output().status.success() && self.rejected.is_none()
+    }
+
+    /// Returns the output of the completed command, if it succeeded.
+    fn into_success(self) -> Result<RunOutcome, ExecutionError> {
+        if self.succeeded() {
+            Ok(self.into_outcome())
+        } else {
+            Err(self.into_failure())
+        }
+    }
+
+    /// Converts the completed (unsuccessful) command into an error.
+    fn into_failure(self) -> ExecutionError {
+        let command = self.argv();
+        let timed_out = self.timed_out;
+        let rejected = self.rejected.clone();
+        let output = self.into_output();
+        if let Some(reason) = rejected {
+            return ExecutionError::Unexpected {
+                reason,
+                command,
+                status: output.status,
+                stdout: output.stdout,
+                stderr: output.stderr,
+            };
+        }
+        match timed_out {
+            Some(timeout) => ExecutionError::TimedOut {
+                command,
+                timeout,
+                stdout: output.stdout,
+                stderr: output.stderr,
+            },
+            None => ExecutionError::NonZeroExit {
+                command,
+                status: output.status,
+                stdout: output.stdout,
+                stderr: output.stderr,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Execution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let command = self.argv();
+        let color = self.color.enabled();
+        if let Some(timeout) = self.timed_out {
+            writeln!(f, "Timed out after {:?}", timeout)?;
+        }
+        if let Some(reason) = &self.rejected {
+            writeln!(f, "{}", reason)?;
+        }
+        let (status, stdout, stderr) = match self.result.as_ref() {
+            // The status of a killed process is uninteresting.
+            Some(out) if self.timed_out.is_some() => (None, &out.stdout[..], &out.stderr[..]),
+            Some(out) => (Some(&out.status), &out.stdout[..], &out.stderr[..]),
+            None => (None, &[][..], &[][..]),
+        };
+        fmt_execution(
+            f,
+            color,
+            &command,
+            status,
+            StreamContents::new(&self.stdout, stdout, self.stdout_truncated),
+            StreamContents::new(&self.stderr, stderr, self.stderr_truncated),
+            self.output_tail,
+        )?;
+        if self.inherited_stdio {
+            write!(f, "\n(stdio inherited; output not captured)")?;
+        }
+        if self.attempts > 1 && !self.succeeded() {
+            write!(f, "\n(failed after {} attempts)", self.attempts)?;
+        }
+        Ok(())
+    }
+}
+
+/// The contents of an output stream, as reported by [`fmt_execution`].
+enum StreamContents<'a> {
+    /// The stream was captured.
+    Captured(&'a [u8]),
+    /// The stream was captured, but truncated to the given bytes.
+    Truncated(&'a [u8]),
+    /// The stream was written to a file.
+    File(&'a Path),
+    /// The stream was inherited or discarded.
+    Uncaptured,
+}
+
+impl<'a> StreamContents<'a> {
+    /// A stream written according to `mode`, of which `captured` was
+    /// captured, discarding the remainder if `truncated`.
+    fn new(mode: &'a StdioMode, captured: &'a [u8], truncated: bool) -> Self {
+        match mode {
+            StdioMode::Capture if truncated => StreamContents::Truncated(captured),
+            StdioMode::Capture => StreamContents::Captured(captured),
+            StdioMode::Inherit | StdioMode::Null => StreamContents::Uncaptured,
+            StdioMode::File(path) => StreamContents::File(path),
+        }
+    }
+}
+
+/// Pretty-prints a command, along with the result of its execution.
+fn fmt_execution(
+    f: &mut fmt::Formatter,
+    color: bool,
+    command: &[OsString],
+    status: Option<&ExitStatus>,
+    stdout: StreamContents,
+    stderr: StreamContents,
+    tail: Option<usize>,
+) -> fmt::Result {
+    color::paint(f, color, color::COMMAND, command_line(command))?;
+    if let Some(status) = status {
+        if status.code().is_none() {
+            write!(f, "\n{}", describe_termination(status))?;
+        } else if !status.success() {
+            write!(f, "\n{}", status)?;
+        }
+    }
+    for (name, stream, code) in [
+        ("stdout", stdout, color::STDOUT),
+        ("stderr", stderr, color::STDERR),
+    ] {
+        match stream {
+            StreamContents::Captured([]) => (),
+            StreamContents::Captured(bytes) => {
+                writeln!(f)?;
+                fmt_tail(f, color, code, bytes, tail)?;
+            }
+            StreamContents::Truncated(bytes) => {
+                writeln!(f)?;
+                fmt_tail(f, color, code, bytes, tail)?;
+                write!(f, "\n... ({} truncated at {} bytes)", name, bytes.len())?;
+            }
+            StreamContents::File(path) => write!(f, "\n({} written to {})", name, path.display())?,
+            StreamContents::Uncaptured => (),
+        }
+    }
+    Ok(())
+}
+
+/// Writes the last `tail` lines of `output` (or all of it, if unset),
+/// preceded by a note of how many lines were omitted.
+fn fmt_tail(
+    f: &mut fmt::Formatter,
+    color: bool,
+    code: &str,
+    output: &[u8],
+    tail: Option<usize>,
+) -> fmt::Result {
+    let output = String::from_utf8_lossy(output);
+    let total = output.lines().count();
+    let omitted = match tail {
+        Some(tail) if tail < total => total - tail,
+        _ => return color::paint(f, color, code, output),
+    };
+    let start = output
+        .match_indices('\n')
+        .nth(omitted - 1)
+        .map_or(output.len(), |(i, _)| i + 1);
+    writeln!(f, "... ({} earlier lines omitted)", omitted)?;
+    color::paint(f, color, code, &output[start..])
+}
+
+/// Describes an unsuccessful execution, as reported when [`Executor::run`]
+/// panics: the command line, how it terminated, and its output.
+fn failure_message(exec: &Execution) -> String {
+    exec.to_string()
+}
+
+/// Describes why `path` does not exist, listing the contents of its
+/// parent directory, if it does not.
+fn missing_file(path: &Path) -> Option<String> {
+    if path.exists() {
+        return None;
+    }
+    let dir = match path.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+        Some(dir) => dir,
+        None => return Some(format!("Expected {} to exist", path.display())),
+    };
+    let mut names: Vec<String> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.file_name().to_string_lossy().into_owned()))
+            .collect(),
+        Err(err) => {
+            return Some(format!(
+                "Expected {} to exist, but {} could not be read: {}",
+                path.display(),
+                dir.display(),
+                err
+            ))
+        }
+    };
+    names.sort();
+    let mut reason = format!(
+        "Expected {} to exist; contents of {}:",
+        path.display(),
+        dir.display()
+    );
+    if names.is_empty() {
+        reason.push_str(" (empty)");
+    }
+    for name in names {
+        reason.push_str("\n  ");
+        reason.push_str(&name);
+    }
+    Some(reason)
+}
+
+/// Adapts a predicate over the output into a check, for
+/// [`Executor::run_asserting`].
+fn satisfying<F>(predicate: F) -> impl FnOnce(&RunOutcome) -> Result<(), &'static str>
+where
+    F: FnOnce(&RunOutcome) -> bool,
+{
+    move |outcome| {
+        if predicate(outcome) {
+            Ok(())
+        } else {
+            Err("predicate returned false")
+        }
+    }
+}
+
+/// Panics with `msg`, prefixed by the context `label`, if any.
+#[track_caller]
+fn panic_in_context(label: Option<&str>, msg: fmt::Arguments) -> ! {
+    match label {
+        Some(label) => panic!("[{}] {}", label, msg),
+        None => panic!("{}", msg),
+    }
+}
+
+/// Describes how the stdout of the command `a` differs from that of `b`
+/// (once normalized, if `normalize` is set), if it does.
+fn stdout_difference(
+    a: &[OsString],
+    a_stdout: &[u8],
+    b: &[OsString],
+    b_stdout: &[u8],
+    normalize: Option<&dyn Fn(&str) -> String>,
+) -> Option<String> {
+    let text_a = String::from_utf8_lossy(a_stdout);
+    let text_b = String::from_utf8_lossy(b_stdout);
+    let (text_a, text_b, same) = match normalize {
+        Some(normalize) => {
+            let (text_a, text_b) = (normalize(&text_a), normalize(&text_b));
+            let same = text_a == text_b;
+            (text_a.into(), text_b.into(), same)
+        }
+        None => (text_a, text_b, a_stdout == b_stdout),
+    };
+    if same {
+        return None;
+    }
+    let mut diff = diff::unified(&text_a, &text_b, "a", "b");
+    if diff.is_empty() {
+        // The difference is only in line endings.
+        diff = format!("-{:?}\n+{:?}\n", text_a, text_b);
+    }
+    Some(format!(
+        "Expected commands to write the same stdout:\na: {}\nb: {}\n{}",
+        command_line(a),
+        command_line(b),
+        diff
+    ))
+}
+
+/// Unwraps results, panicking with the pretty-printed error as
+/// [`Executor::fail`] does.
+///
+/// Unlike `unwrap_or_else(|err| executor.fail(err))`, the panic is
+/// reported at the location of the caller, as closures do not forward
+/// the location of theirs.
+trait OrFail<T> {
+    fn or_fail<K, V>(self, executor: &Executor<K, V>) -> T
+    where
+        K: AsRef<OsStr> + Clone,
+        V: AsRef<OsStr> + Clone;
+}
+
+impl<T> OrFail<T> for Result<T, ExecutionError> {
+    #[track_caller]
+    fn or_fail<K, V>(self, executor: &Executor<K, V>) -> T
+    where
+        K: AsRef<OsStr> + Clone,
+        V: AsRef<OsStr> + Clone,
+    {
+        match self {
+            Ok(value) => value,
+            Err(err) => executor.fail(err),
+        }
+    }
+}
+
+/// Returns the arguments to `sudo` preceding the command, preserving the
+/// variables of `env` for it.
+#[cfg(unix)]
+fn sudo_args(env: &[(OsString, OsString)]) -> Vec<OsString> {
+    let mut args = vec![OsString::from("-n")];
+    if !env.is_empty() {
+        let mut preserved = OsString::from("--preserve-env=");
+        for (index, (key, _)) in env.iter().enumerate() {
+            if index > 0 {
+                preserved.push(",");
+            }
+            preserved.push(key);
+        }
+        args.push(preserved);
+    }
+    args.push("--".into());
+    args
+}
+
+/// Converts `path` to a C string, unless it contains a NUL byte.
+#[cfg(unix)]
+fn c_path(path: &Path) -> Option<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes()).ok()
+}
+
+/// Panics if `key` cannot be used as the name of an environment variable.
+///
+/// `Command::env` does not reject such keys, but the variable would not
+/// be observed as intended by the process.
+#[track_caller]
+fn validate_key(key: &OsStr) {
+    if key.as_encoded_bytes().iter().any(|&b| b == b'=' || b == 0) {
+        panic!(
+            "Invalid environment variable {:?}: keys may not contain '=' or NUL",
+            key
+        );
+    }
+}
+
+/// Describes the first line of `output` which differs from `expected`,
+/// if any.
+fn line_mismatch<L, T>(output: &[u8], expected: L) -> Option<String>
+where
+    L: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    let output = String::from_utf8_lossy(output);
+    let mut actual = output.lines();
+    let mut expected = expected.into_iter();
+    for line in 1.. {
+        match (expected.next(), actual.next()) {
+            (None, None) => break,
+            (Some(e), Some(a)) if e.as_ref() == a => continue,
+            (e, a) => {
+                return Some(format!(
+                    "line {} to be {:?}, but got {:?}",
+                    line,
+                    e.as_ref().map(AsRef::as_ref),
+                    a
+                ))
+            }
+        }
+    }
+    None
+}
+
+/// Returns true if `path` is a file which may be executed.
+fn is_executable(path: &Path) -> bool {
+    match std::fs::metadata(path) {
+        #[cfg(unix)]
+        Ok(metadata) => {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+        }
+        #[cfg(not(unix))]
+        Ok(metadata) => metadata.is_file(),
+        Err(_) => false,
+    }
+}
+
+/// Returns true if `needle` appears anywhere within `haystack`.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty()
+        || haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+}
+
+/// Returns the signal which terminated a process, if any.
+fn termination_signal(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
+    }
+}
+
+/// Describes how a process terminated without an exit code.
+#[cfg(unix)]
+fn describe_termination(status: &ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+    let signal = match status.signal() {
+        Some(signal) => signal,
+        None => return "terminated without an exit code".to_string(),
+    };
+    let mut description = match signal_name(signal) {
+        Some(name) => format!("terminated by signal {} ({})", name, signal),
+        None => format!("terminated by signal {}", signal),
+    };
+    if status.core_dumped() {
+        description.push_str(" (core dumped)");
+    }
+    description
+}
+
+/// Duplicates `fd` as `child_fd`, in a child which is about to execute,
+/// such that it remains open once the child executes.
+#[cfg(unix)]
+fn inherit_fd(fd: RawFd, child_fd: RawFd) -> io::Result<()> {
+    // SAFETY: These calls have no memory-safety requirements; failures
+    // are reported through their return values.
+    let result = unsafe {
+        if fd == child_fd {
+            // `dup2` would do nothing, leaving close-on-exec set.
+            let flags = libc::fcntl(fd, libc::F_GETFD);
+            if flags < 0 {
+                flags
+            } else {
+                libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC)
+            }
+        } else {
+            libc::dup2(fd, child_fd)
+        }
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns the conventional name of `signal`, if it is a standard signal.
+#[cfg(unix)]
+fn signal_name(signal: i32) -> Option<&'static str> {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGCHLD => "SIGCHLD",
+        libc::SIGCONT => "SIGCONT",
+        libc::SIGSTOP => "SIGSTOP",
+        libc::SIGTSTP => "SIGTSTP",
+        libc::SIGTTIN => "SIGTTIN",
+        libc::SIGTTOU => "SIGTTOU",
+        libc::SIGURG => "SIGURG",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGVTALRM => "SIGVTALRM",
+        libc::SIGPROF => "SIGPROF",
+        libc::SIGWINCH => "SIGWINCH",
+        libc::SIGIO => "SIGIO",
+        libc::SIGSYS => "SIGSYS",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Describes how a process terminated without an exit code.
+#[cfg(not(unix))]
+fn describe_termination(_status: &ExitStatus) -> String {
+    "terminated without an exit code".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The variables of an executor configured with `env`.
+    fn given<K, V>(env: Vec<(K, V)>) -> Vec<(EnvKey<K>, V)> {
+        env.into_iter()
+            .map(|(key, value)| (EnvKey::Given(key), value))
+            .collect()
+    }
+
+    #[test]
+    fn export_right_environment_variable() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run(vec!["/bin/bash", "-c", "[ \"$FOO\" == \"BAR\" ]"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn export_wrong_environment_variable() {
+        let executor = Executor::new(vec![("FOO", "BAZINGA")]);
+        executor.run(vec!["/bin/bash", "-c", "[ \"$FOO\" == \"BAR\" ]"]);
+    }
+
+    #[test]
+    fn run_returns_captured_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    fn with_env_overrides_earlier_value() {
+        let executor = Executor::new(vec![("FOO", "BAZINGA")]).with_env("FOO", "BAR");
+        executor.run(vec!["/bin/bash", "-c", "[ \"$FOO\" == \"BAR\" ]"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid environment variable \"FOO=BAR\"")]
+    fn new_rejects_key_containing_equals() {
+        Executor::new(vec![("FOO=BAR", "BAZ")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid environment variable \"FOO\\0\"")]
+    fn with_env_rejects_key_containing_nul() {
+        Executor::new(vec![("FOO", "BAR")]).with_env("FOO\0", "BAZ");
+    }
+
+    #[test]
+    fn from_map_collects_environment() {
+        let map: std::collections::BTreeMap<_, _> =
+            vec![("FOO", "BAR"), ("BAZ", "QUX")].into_iter().collect();
+        let executor = Executor::from_map(map);
+        assert_eq!(executor.env, given(vec![("BAZ", "QUX"), ("FOO", "BAR")]));
+
+        let map: std::collections::HashMap<_, _> = vec![("FOO", "BAR")].into_iter().collect();
+        let output = Executor::from_map(map).run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    fn default_string_executor() {
+        fn fixture() -> StringExecutor {
+            StringExecutor::default().with_env("FOO".to_string(), "BAR".to_string())
+        }
+        let output = fixture().run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    fn debug_redacts_env_values() {
+        let executor = Executor::new(vec![("TOKEN", "hunter2")]).with_current_dir("/tmp");
+        let debug = format!("{:?}", executor);
+        assert!(
+            debug.starts_with("Executor { env: [TOKEN=***], "),
+            "{}",
+            debug
+        );
+        assert!(!debug.contains("hunter2"), "{}", debug);
+        let debug = format!("{:?}", executor.with_debug_show_values());
+        assert!(debug.contains("env: [TOKEN=hunter2]"), "{}", debug);
+    }
+
+    #[test]
+    fn clone_and_specialize() {
+        let base = Executor::new(vec![("FOO", "BAZINGA")]).with_stdin("BAZ");
+        let special = base.clone().with_env("FOO", "BAR");
+        let output = special.run(vec!["/bin/bash", "-c", "echo $FOO; cat"]);
+        assert_eq!(output.stdout, b"BAR\nBAZ");
+        let output = base.run(vec!["/bin/bash", "-c", "echo $FOO; cat"]);
+        assert_eq!(output.stdout, b"BAZINGA\nBAZ");
+    }
+
+    #[test]
+    fn merge_environments() {
+        let base = Executor::new(vec![("FOO", "BAZINGA"), ("BAZ", "QUX")]);
+        let executor = base.merge(Executor::new(vec![("FOO", "BAR"), ("NEW", "VAL")]));
+        assert_eq!(
+            executor.env,
+            given(vec![("BAZ", "QUX"), ("FOO", "BAR"), ("NEW", "VAL")])
+        );
+        executor.run(vec![
+            "/bin/bash",
+            "-c",
+            "[ \"$FOO $BAZ $NEW\" == \"BAR QUX VAL\" ]",
+        ]);
+    }
+
+    #[test]
+    fn run_stdout_trims_newline() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let stdout = executor.run_stdout(vec!["/bin/bash", "-c", "echo \" $FOO\"; echo"]);
+        assert_eq!(stdout, " BAR");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected stdout to be valid UTF-8")]
+    fn run_stdout_invalid_utf8() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_stdout(vec!["/bin/bash", "-c", "printf '\\xff'"]);
+    }
+
+    #[test]
+    fn run_observing_returns_any_outcome() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_retries(2, Duration::ZERO);
+        let output = executor.run_observing(vec![
+            "/bin/bash",
+            "-c",
+            "echo $FOO; echo x >>/dev/stderr; exit 4",
+        ]);
+        assert_eq!(output.code(), Some(4));
+        assert_eq!(output.stdout, b"BAR\n");
+        assert_eq!(output.stderr, b"x\n");
+        assert!(executor.run_observing(vec!["/bin/true"]).status.success());
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to spawn `/nonexistent`")]
+    fn run_observing_panics_on_spawn_failure() {
+        Executor::new(vec![("FOO", "BAR")]).run_observing(vec!["/nonexistent"]);
+    }
+
+    #[test]
+    fn run_program_with_separate_args() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output = executor.run_program("/bin/bash", ["-c", "echo $FOO $0", "arg"]);
+        assert_eq!(output.stdout, b"BAR arg\n");
+        let output = executor.run_program("/bin/true", Vec::<&str>::new());
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn try_run_stdout_invalid_utf8() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        assert_eq!(
+            executor
+                .try_run_stdout(vec!["/bin/bash", "-c", "echo $FOO"])
+                .unwrap(),
+            "BAR"
+        );
+        match executor.try_run_stdout(vec!["/bin/bash", "-c", "printf 'ok\\xffok'"]) {
+            Err(err @ ExecutionError::InvalidUtf8 { .. }) => {
+                assert_eq!(
+                    err.to_string(),
+                    "Command wrote invalid UTF-8 to stdout, after 2 valid bytes"
+                );
+                let ExecutionError::InvalidUtf8 {
+                    stream,
+                    valid_up_to,
+                    raw,
+                } = err
+                else {
+                    unreachable!()
+                };
+                assert_eq!(stream, Stream::Stdout);
+                assert_eq!(&raw[..valid_up_to], b"ok");
+                assert_eq!(raw, b"ok\xffok");
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn run_shell_exports_environment_variable() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        #[cfg(windows)]
+        let (script, expected) = ("echo %FOO%", &b"BAR\r\n"[..]);
+        #[cfg(not(windows))]
+        let (script, expected) = ("echo $FOO", &b"BAR\n"[..]);
+        assert_eq!(executor.run_shell(script).stdout, expected);
+    }
+
+    #[test]
+    #[cfg_attr(not(windows), should_panic(expected = "exit status: 3"))]
+    #[cfg_attr(windows, should_panic(expected = "exit code: 3"))]
+    fn run_shell_failure() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_shell("exit 3");
+    }
+
+    #[test]
+    fn run_with_env_overrides_for_one_run() {
+        let executor = Executor::new(vec![("FOO", "BAZINGA"), ("BAZ", "QUX")]);
+        let output = executor.run_with_env(
+            vec!["/bin/bash", "-c", "echo $FOO $BAZ"],
+            vec![("FOO", "BAR")],
+        );
+        assert_eq!(output.stdout, b"BAR QUX\n");
+        assert_eq!(
+            executor.env,
+            given(vec![("FOO", "BAZINGA"), ("BAZ", "QUX")])
+        );
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        assert_eq!(output.stdout, b"BAZINGA\n");
+    }
+
+    #[test]
+    fn from_current_env_snapshots_environment() {
+        let executor = Executor::from_current_env()
+            .clear_env()
+            .with_env("FOO".to_string(), "BAR".to_string());
+        let path = std::env::var("PATH").unwrap();
+        let output = executor.run(vec!["/bin/bash", "-c", "echo \"$PATH $FOO\""]);
+        assert_eq!(output.stdout, format!("{} BAR\n", path).into_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "dotenv")]
+    fn from_dotenv_loads_variables() {
+        let path =
+            std::env::temp_dir().join(format!("test_process_executor-{}.env", std::process::id()));
+        std::fs::write(&path, "# Config\nFOO=bar\nexport BAZ=\"qux quux\"\n").unwrap();
+        let executor = Executor::from_dotenv(&path);
+        std::fs::write(&path, "FOO=bar\nBAZ\n").unwrap();
+        let result = std::panic::catch_unwind(|| Executor::from_dotenv(&path));
+        std::fs::remove_file(&path).unwrap();
+        let output = executor
+            .with_env("FOO".to_string(), "override".to_string())
+            .run(vec!["/bin/bash", "-c", "echo $FOO $BAZ"]);
+        assert_eq!(output.stdout, b"override qux quux\n");
+        let err = result.unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.ends_with(": line 2: expected KEY=VALUE"), "{}", msg);
+    }
+
+    #[test]
+    fn clear_env_hides_inherited_variables() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).clear_env();
+        let output = executor.run(vec!["/usr/bin/env"]);
+        assert_eq!(output.stdout, b"FOO=BAR\n");
+    }
+
+    #[test]
+    fn keep_only_env_inherits_named_variables() {
+        let executor =
+            Executor::new(vec![("FOO", "BAR")]).keep_only_env(["PATH", "UNSET_VARIABLE"]);
+        let output = executor.run(vec!["/usr/bin/env"]);
+        let mut vars: Vec<String> = output.stdout_str().lines().map(String::from).collect();
+        vars.sort();
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(vars, vec!["FOO=BAR".to_string(), format!("PATH={}", path)]);
+    }
+
+    #[test]
+    fn with_locale_sets_locale_variables() {
+        let executor = Executor::new(vec![
+            ("FOO", "BAR"),
+            ("LC_TIME", "fr_FR.UTF-8"),
+            ("LANG", "de_DE"),
+        ])
+        .with_locale("C");
+        let script = "echo $FOO $LC_ALL $LANG ${LC_TIME-unset}; printf 'b\\nB\\na\\n' | sort";
+        let output = executor.run(vec!["/bin/bash", "-c", script]);
+        assert_eq!(output.stdout, b"BAR C C unset\nB\na\nb\n");
+
+        let output = executor.run_with_env(
+            vec!["/bin/bash", "-c", "echo $LC_ALL"],
+            vec![("LC_ALL", "POSIX")],
+        );
+        assert_eq!(output.stdout, b"POSIX\n");
+    }
+
+    #[test]
+    fn with_locale_replaces_locale_and_configured_variables() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_locale("en_US.UTF-8")
+            .with_env("LC_NUMERIC", "de_DE")
+            .with_locale("C");
+        assert_eq!(executor.removed_env.len(), LOCALE_VARS.len());
+        let script = "echo $FOO $LC_ALL ${LC_NUMERIC-unset}";
+        let output = executor.run(vec!["/bin/bash", "-c", script]);
+        assert_eq!(output.stdout, b"BAR C unset\n");
+    }
+
+    #[test]
+    fn with_env_lazy_computes_value_per_launch() {
+        let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let executor = Executor::new(vec![("FOO", "BAR"), ("TOKEN", "static")]).with_env_lazy({
+            let counter = counter.clone();
+            move || {
+                let n = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                ("TOKEN".into(), format!("token-{}", n).into())
+            }
+        });
+        let script = vec!["/bin/bash", "-c", "echo $FOO $TOKEN"];
+        let first = executor.run(&script).stdout;
+        let second = executor.run(&script).stdout;
+        assert!(first.starts_with(b"BAR token-"));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn without_env_removes_inherited_variable() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .without_env("PATH")
+            .without_env("FOO");
+        let output = executor.run(vec!["/usr/bin/env"]);
+        let env = String::from_utf8(output.stdout).unwrap();
+        assert!(env.lines().any(|line| line == "FOO=BAR"));
+        assert!(!env.lines().any(|line| line.starts_with("PATH=")));
+    }
+
+    #[test]
+    fn run_in_current_dir() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_current_dir(&dir);
+        let output = executor.run(vec!["/bin/pwd", "-P"]);
+        assert_eq!(output.stdout, format!("{}\n", dir.display()).into_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn run_capture_redacted_scrubs_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let redactions = [
+            (regex::Regex::new(r"pid \d+").unwrap(), "pid [PID]"),
+            (regex::Regex::new(r"(\w+)=BAR").unwrap(), "$1=[REDACTED]"),
+        ];
+        let output = executor.run_capture_redacted(
+            vec!["/bin/bash", "-c", "echo pid $$; echo FOO=$FOO"],
+            &redactions,
+        );
+        assert_eq!(output, "pid [PID]\nFOO=[REDACTED]\n");
+    }
+
+    #[test]
+    fn run_with_env_fn() {
+        let executor = Executor::new(vec![("FOO", "BAR"), ("BAZ", "QUX")]).with_env_fn(|env| {
+            env.retain(|(key, _)| key != "BAZ");
+            env.push(("DYNAMIC".into(), std::process::id().to_string().into()));
+        });
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO $BAZ $DYNAMIC"]);
+        assert_eq!(
+            output.stdout,
+            format!("BAR {}\n", std::process::id()).into_bytes()
+        );
+    }
+
+    #[test]
+    fn run_with_path_resolves_programs() {
+        let executor = Executor::new(vec![("PATH", "/nonexistent")])
+            .clear_env()
+            .with_path(vec!["/nonexistent", "/usr/bin", "/bin"]);
+        let output = executor.run(vec!["sh", "-c", "echo $PATH"]);
+        assert_eq!(output.stdout, b"/nonexistent:/usr/bin:/bin\n");
+    }
+
+    #[test]
+    fn which_resolves_programs() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        assert_eq!(executor.which("/bin/sh"), Some(PathBuf::from("/bin/sh")));
+        assert!(executor.which("sh").unwrap().ends_with("sh"));
+        assert_eq!(executor.which("test_process_executor-missing"), None);
+        assert_eq!(executor.with_path(["/"]).which("sh"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "None of the PATH directories exist")]
+    fn with_path_rejects_missing_dirs() {
+        Executor::new(vec![("FOO", "BAR")]).with_path(vec!["/nonexistent"]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn run_json_parses_stdout() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output: std::collections::BTreeMap<String, Vec<u32>> =
+            executor.run_json(vec!["/bin/bash", "-c", r#"echo '{"'$FOO'": [1, 2]}'"#]);
+        assert_eq!(output["BAR"], [1, 2]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    #[should_panic(expected = "Failed to parse stdout as")]
+    fn run_json_reports_invalid_json() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_json::<Vec<u32>, _, _>(vec!["/bin/echo", "not json"]);
+    }
+
+    #[test]
+    fn run_with_stdout_sink() {
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stdout_sink(buffer.clone());
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        executor.run(vec!["/bin/echo", "BAZ"]);
+        assert_eq!(output.stdout, b"BAR\n");
+        assert_eq!(*buffer.0.lock().unwrap(), b"BAR\nBAZ\n");
+    }
+
+    #[test]
+    fn try_run_expecting_code_reports_mismatch() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let args = vec!["/bin/bash", "-c", "echo $FOO; exit 3"];
+        assert_eq!(
+            executor
+                .try_run_expecting_code(args.clone(), 3)
+                .unwrap()
+                .stdout,
+            b"BAR\n"
+        );
+        match executor.try_run_expecting_code(args, 2) {
+            Err(ExecutionError::Unexpected { reason, stdout, .. }) => {
+                assert_eq!(reason, "Expected exit code 2, but got 3");
+                assert_eq!(stdout, b"BAR\n");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_run_expecting_stdout_contains_reports_failures() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        assert!(matches!(
+            executor.try_run_expecting_stdout_contains(vec!["/bin/echo", "BAZ"], "BAR"),
+            Err(ExecutionError::Unexpected { .. })
+        ));
+        assert!(matches!(
+            executor.try_run_expecting_stdout_contains(vec!["/bin/false"], "BAR"),
+            Err(ExecutionError::NonZeroExit { .. })
+        ));
+    }
+
+    #[test]
+    fn run_with_arg_interpolation() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_arg_interpolation();
+        let output = executor.run_with_env(
+            vec!["/bin/echo", "$FOO/bin", "${BAZ}", "$$FOO"],
+            [("BAZ", "QUX")],
+        );
+        assert_eq!(output.stdout, b"BAR/bin QUX $FOO\n");
+        assert!(matches!(
+            executor.try_run(vec!["/bin/echo", "$UNDEFINED_VARIABLE"]),
+            Err(ExecutionError::UndefinedVariable { name, .. }) if name == "UNDEFINED_VARIABLE"
+        ));
+        let output = executor
+            .with_empty_undefined_vars()
+            .run(vec!["/bin/echo", "a${UNDEFINED_VARIABLE}b"]);
+        assert_eq!(output.stdout, b"ab\n");
+    }
+
+    #[test]
+    fn run_within_budget() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_timeout(Duration::from_millis(1));
+        let output = executor.run_within(
+            vec!["/bin/bash", "-c", "echo $FOO"],
+            Duration::from_secs(10),
+        );
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Exceeded time budget of 100ms")]
+    fn run_within_exceeded_budget() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_within(vec!["/bin/sleep", "10"], Duration::from_millis(100));
+    }
+
+    #[test]
+    fn run_with_configures_command() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR"), ("BAZ", "QUX")]);
+        let output = executor.run_with(vec!["/bin/bash", "-c", "echo $FOO $BAZ; pwd"], |command| {
+            command.current_dir(&dir).env("BAZ", "override");
+        });
+        assert_eq!(
+            output.stdout_str(),
+            format!("BAR override\n{}\n", dir.display())
+        );
+    }
+
+    #[test]
+    fn run_in_overrides_current_dir() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_current_dir("/");
+        let output = executor.run_in(&dir, vec!["/bin/pwd", "-P"]);
+        assert_eq!(output.stdout, format!("{}\n", dir.display()).into_bytes());
+        let output = executor.run(vec!["/bin/pwd", "-P"]);
+        assert_eq!(output.stdout, b"/\n");
+    }
+
+    #[test]
+    fn run_expecting_matching_code() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output = executor.run_expecting_code(vec!["/bin/bash", "-c", "echo $FOO; exit 2"], 2);
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected exit code 2, but got 0")]
+    fn run_expecting_mismatched_code() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_code(vec!["/bin/bash", "-c", "exit 0"], 2);
+    }
+
+    #[test]
+    fn run_expecting_allowed_codes() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_codes(vec!["/bin/bash", "-c", "exit 0"], &[0, 1]);
+        executor.run_expecting_codes(vec!["/bin/bash", "-c", "exit 1"], &[0, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected exit code in [0, 1], but got 2")]
+    fn run_expecting_disallowed_code() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_codes(vec!["/bin/bash", "-c", "exit 2"], &[0, 1]);
+    }
+
+    #[test]
+    fn run_expecting_failure_returns_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output =
+            executor.run_expecting_failure(vec!["/bin/bash", "-c", "echo $FOO >&2; exit 3"]);
+        assert_eq!(output.code(), Some(3));
+        assert_eq!(output.stderr, b"BAR\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected failure, but the command succeeded")]
+    fn run_expecting_failure_succeeds() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_failure(vec!["/bin/true"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "terminated by signal SIGKILL (9)")]
+    fn run_expecting_code_killed_by_signal() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_code(vec!["/bin/bash", "-c", "kill -9 $$"], 0);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn run_measures_peak_rss() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let script = "x=$(head -c 50000000 /dev/zero | tr '\\0' a); echo ${#x}";
+        let large = executor.run(vec!["/bin/bash", "-c", script]);
+        assert_eq!(large.stdout, b"50000000\n");
+        assert!(large.peak_rss_bytes().unwrap() > 50_000_000);
+
+        let timed = executor.clone().with_timeout(Duration::from_secs(10));
+        assert!(timed.run(vec!["/bin/true"]).peak_rss_bytes().is_some());
+    }
+
+    #[test]
+    fn run_expecting_termination() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting(vec!["/bin/true"], Expectation::Success);
+        executor.run_expecting(vec!["/bin/false"], Expectation::Failure);
+        executor.run_expecting(vec!["/bin/bash", "-c", "exit 3"], Expectation::Code(3));
+        executor.run_expecting(
+            vec!["/bin/bash", "-c", "exit 3"],
+            Expectation::Codes(vec![2, 3]),
+        );
+        executor.run_expecting(
+            vec!["/bin/bash", "-c", "kill -TERM $$"],
+            Expectation::Signal(libc::SIGTERM),
+        );
+        executor
+            .clone()
+            .with_timeout(Duration::from_millis(100))
+            .run_expecting(vec!["/bin/sleep", "10"], Expectation::Timeout);
+    }
+
+    #[test]
+    fn try_run_expecting_reports_mismatch() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_color(ColorChoice::Never);
+        let reason = |result: Result<RunOutcome, ExecutionError>| match result {
+            Err(ExecutionError::Unexpected { reason, .. }) => reason,
+            result => panic!("Unexpected result: {:?}", result),
+        };
+        assert_eq!(
+            reason(
+                executor.try_run_expecting(vec!["/bin/true"], Expectation::Signal(libc::SIGTERM))
+            ),
+            "Expected termination by signal SIGTERM (15), but exited with code 0"
+        );
+        assert_eq!(
+            reason(executor.try_run_expecting(vec!["/bin/true"], Expectation::Timeout)),
+            "Expected a timeout, but exited with code 0"
+        );
+        assert_eq!(
+            reason(executor.try_run_expecting(
+                vec!["/bin/bash", "-c", "kill -KILL $$"],
+                Expectation::Code(0)
+            )),
+            "Expected exit code 0, but terminated by signal SIGKILL (9)"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "terminated by signal SIGSEGV (11)")]
+    fn run_reports_signal_name() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run(vec!["/bin/bash", "-c", "ulimit -c 0; kill -SEGV $$"]);
+    }
+
+    #[test]
+    fn run_within_timeout() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_timeout(Duration::from_secs(10));
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Timed out after 100ms")]
+    fn run_exceeding_timeout() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_timeout(Duration::from_millis(100));
+        executor.run(vec!["/bin/sleep", "10"]);
+    }
+
+    #[test]
+    fn timeout_signals_before_killing() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_timeout(Duration::from_millis(200))
+            .with_timeout_kill(libc::SIGTERM, Duration::from_secs(5));
+        let script = "trap 'echo cleanup; exit 0' TERM; echo $FOO; sleep 10 & wait";
+        match executor.try_run(vec!["/bin/bash", "-c", script]) {
+            Err(ExecutionError::TimedOut { stdout, .. }) => assert_eq!(stdout, b"BAR\ncleanup\n"),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        // Processes which ignore the signal are killed after the grace period.
+        let executor = executor.with_timeout_kill(libc::SIGTERM, Duration::from_millis(200));
+        let started = Instant::now();
+        let script = "trap '' TERM; sleep 10 & wait";
+        assert!(executor.try_run(vec!["/bin/bash", "-c", script]).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn try_run_reports_timeout_with_partial_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_timeout(Duration::from_millis(200));
+        match executor.try_run(vec!["/bin/bash", "-c", "echo $FOO; exec sleep 10"]) {
+            Err(ExecutionError::TimedOut { stdout, .. }) => assert_eq!(stdout, b"BAR\n"),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn run_in_new_process_group() {
+        let script = "[ $$ -eq $(ps -o pgid= -p $$) ]";
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_code(vec!["/bin/bash", "-c", script], 1);
+        executor
+            .with_new_process_group()
+            .run(vec!["/bin/bash", "-c", script]);
+    }
+
+    #[test]
+    fn pre_run_hook_observes_each_launch() {
+        let launched = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = launched.clone();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_pre_run_hook(move |argv| {
+            recorded.lock().unwrap().push(command_line(argv));
+        });
+        executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        executor.try_run(vec!["/bin/false"]).unwrap_err();
+        executor.spawn(vec!["/bin/true"]).wait();
+        assert_eq!(
+            *launched.lock().unwrap(),
+            vec!["/bin/bash -c 'echo $FOO'", "/bin/false", "/bin/true"]
+        );
+    }
+
+    #[test]
+    fn post_run_hook_observes_successful_output() {
+        let outputs = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = outputs.clone();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_post_run_hook(move |output| {
+            recorded.lock().unwrap().push(output.stdout.clone());
+        });
+        executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        executor
+            .try_run(vec!["/bin/bash", "-c", "echo oops; exit 1"])
+            .unwrap_err();
+        executor.run(vec!["/bin/echo", "BAZ"]);
+        assert_eq!(
+            *outputs.lock().unwrap(),
+            vec![b"BAR\n".to_vec(), b"BAZ\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn run_with_umask() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output = executor
+            .with_umask(0o027)
+            .run(vec!["/bin/bash", "-c", "umask"]);
+        assert_eq!(output.stdout, b"0027\n");
+    }
+
+    #[test]
+    fn run_with_rlimit() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_rlimit(Rlimit::NoFile, 16, 32);
+        let output = executor.run(vec!["/bin/bash", "-c", "ulimit -Sn; ulimit -Hn"]);
+        assert_eq!(output.stdout, b"16\n32\n");
+    }
+
+    #[test]
+    fn run_with_invalid_rlimit() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_rlimit(Rlimit::NoFile, 32, 16);
+        assert!(matches!(
+            executor.try_run(vec!["/bin/true"]),
+            Err(ExecutionError::SpawnFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn run_with_pre_exec() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        // SAFETY: `write` is async-signal-safe.
+        let output = unsafe {
+            executor.clone().with_pre_exec(|| {
+                let msg = b"hook\n";
+                libc::write(libc::STDOUT_FILENO, msg.as_ptr().cast(), msg.len());
+                Ok(())
+            })
+        }
+        .run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        assert_eq!(output.stdout, b"hook\nBAR\n");
+
+        // SAFETY: The closure only constructs an error.
+        let executor =
+            unsafe { executor.with_pre_exec(|| Err(io::Error::from_raw_os_error(libc::EPERM))) };
+        match executor.try_run(vec!["/bin/true"]) {
+            Err(ExecutionError::SpawnFailed { source, .. }) => {
+                assert_eq!(source.raw_os_error(), Some(libc::EPERM));
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn run_with_inherited_fd() {
+        use std::os::unix::io::AsRawFd;
+
+        let path =
+            std::env::temp_dir().join(format!("test_process_executor-{}.fd", std::process::id()));
+        std::fs::write(&path, "BAZ\n").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_inherited_fd(file.as_raw_fd(), 5);
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO; cat <&5"]);
+        assert_eq!(output.stdout, b"BAR\nBAZ\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn run_as_other_user() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_uid(65534)
+            .with_gid(65534);
+        let args = vec!["/usr/bin/id", "-u", "-r"];
+        // SAFETY: `geteuid` cannot fail.
+        if unsafe { libc::geteuid() } == 0 {
+            assert_eq!(executor.run(args).stdout, b"65534\n");
+        } else {
+            match executor.try_run(args) {
+                Err(ExecutionError::SpawnFailed { source, .. }) => {
+                    assert!(source.to_string().contains("typically requires root"))
+                }
+                other => panic!("Unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn run_with_stdin() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stdin("b\na\nc\n");
+        let output = executor.run(vec!["/usr/bin/sort"]);
+        assert_eq!(output.stdout, b"a\nb\nc\n");
+    }
+
+    #[test]
+    fn run_with_large_stdin_and_output() {
+        // Larger than any pipe buffer, so writing stdin only completes if
+        // stdout is read concurrently.
+        let input: Vec<u8> = (0..8 << 20).map(|i| (i % 251) as u8).collect();
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_stdin(input.clone())
+            .with_timeout(Duration::from_secs(60));
+        let output = executor.run(vec!["/bin/cat"]);
+        assert!(output.stdout == input);
+    }
+
+    #[test]
+    fn run_with_stdin_file() {
+        let path = std::env::temp_dir().join(format!(
+            "test_process_executor-{}.stdin",
+            std::process::id()
+        ));
+        std::fs::write(&path, "b\na\nc\n").unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stdin_file(&path);
+        let output = executor.run(vec!["/usr/bin/sort"]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(output.stdout, b"a\nb\nc\n");
+        let err = executor.try_run(vec!["/usr/bin/sort"]).unwrap_err();
+        assert!(err.to_string().contains("Failed to open"), "{}", err);
+    }
+
+    #[test]
+    fn run_without_stdin_reads_eof() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output = executor.run(vec!["/bin/cat"]);
+        assert!(output.stdout.is_empty());
+    }
+
+    #[test]
+    fn run_str_splits_arguments() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output =
+            executor.run_str(r#"/bin/bash -c 'echo "$FOO and  $1"' arg0 "quoted \"arg\"""#);
+        assert_eq!(output.stdout, b"BAR and  quoted \"arg\"\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unbalanced quote")]
+    fn run_str_unbalanced_quotes() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_str("/bin/echo 'oops");
+    }
+
+    #[test]
+    fn display_invalid_utf8_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let err = executor
+            .try_run(vec!["/bin/bash", "-c", "printf 'bad \\xff' >&2; exit 1"])
+            .unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("exit status: 1"), "{}", msg);
+        assert!(msg.contains("bad \u{FFFD}"), "{}", msg);
+    }
+
+    #[test]
+    fn redirect_output_to_files() {
+        let dir = std::env::temp_dir();
+        let stdout = dir.join(format!(
+            "test_process_executor-{}.stdout",
+            std::process::id()
+        ));
+        let stderr = dir.join(format!(
+            "test_process_executor-{}.stderr",
+            std::process::id()
+        ));
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_stdout_file(&stdout)
+            .with_stderr_file(&stderr);
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO; echo oops >&2"]);
+        assert!(output.stdout.is_empty());
+        assert!(output.stderr.is_empty());
+        assert_eq!(std::fs::read(&stdout).unwrap(), b"BAR\n");
+        assert_eq!(std::fs::read(&stderr).unwrap(), b"oops\n");
+
+        let mut exec = Execution::new(vec!["/bin/bash", "-c", "echo $FOO; exit 1"]).unwrap();
+        exec.execute(&executor).unwrap();
+        exec.color = ColorChoice::Never;
+        assert_eq!(
+            exec.to_string(),
+            format!(
+                "/bin/bash -c 'echo $FOO; exit 1'\nexit status: 1\n(stdout written to {})\n(stderr written to {})",
+                stdout.display(),
+                stderr.display()
+            )
+        );
+        std::fs::remove_file(stdout).unwrap();
+        std::fs::remove_file(stderr).unwrap();
+    }
+
+    #[test]
+    fn run_with_stdio_modes() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_stdout(StdioMode::Null)
+            .with_stderr(StdioMode::Capture);
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO; echo oops >&2"]);
+        assert!(output.stdout.is_empty());
+        assert_eq!(output.stderr, b"oops\n");
+
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stderr(StdioMode::Null);
+        let mut exec =
+            Execution::new(vec!["/bin/bash", "-c", "echo $FOO; echo oops >&2; exit 1"]).unwrap();
+        exec.execute(&executor).unwrap();
+        exec.color = ColorChoice::Never;
+        assert_eq!(
+            exec.to_string(),
+            "/bin/bash -c 'echo $FOO; echo oops >&2; exit 1'\nexit status: 1\nBAR\n"
+        );
+    }
+
+    #[test]
+    fn failure_message_describes_execution() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_color(ColorChoice::Never);
+        let mut exec =
+            Execution::new(vec!["/bin/bash", "-c", "echo $FOO; echo oops >&2; exit 3"]).unwrap();
+        exec.execute(&executor).unwrap();
+        assert_eq!(
+            failure_message(&exec),
+            "/bin/bash -c 'echo $FOO; echo oops >&2; exit 3'\nexit status: 3\nBAR\n\noops\n"
+        );
+    }
+
+    #[test]
+    fn execute_describes_completed_execution() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_color(ColorChoice::Never);
+        let completed = executor
+            .execute(vec!["/bin/bash", "-c", "echo $FOO; exit 3"])
+            .unwrap();
+        assert_eq!(completed.program(), "/bin/bash");
+        assert_eq!(completed.args(), ["-c", "echo $FOO; exit 3"]);
+        assert_eq!(completed.output().status.code(), Some(3));
+        assert_eq!(completed.attempts(), 1);
+        assert!(!completed.succeeded());
+        assert_eq!(
+            completed.to_string(),
+            "/bin/bash -c 'echo $FOO; exit 3'\nexit status: 3\nBAR\n"
+        );
+        assert!(matches!(
+            completed.into_result(),
+            Err(ExecutionError::NonZeroExit { .. })
+        ));
+    }
+
+    #[test]
+    fn failure_message_describes_timeout() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_color(ColorChoice::Never)
+            .with_timeout(Duration::from_millis(100));
+        let mut exec = Execution::new(vec!["/bin/bash", "-c", "echo $FOO; exec sleep 10"]).unwrap();
+        exec.execute(&executor).unwrap();
+        assert_eq!(
+            failure_message(&exec),
+            "Timed out after 100ms\n/bin/bash -c 'echo $FOO; exec sleep 10'\nBAR\n"
+        );
+    }
+
+    #[test]
+    fn append_output_to_files() {
+        let stdout = std::env::temp_dir().join(format!(
+            "test_process_executor-{}.append",
+            std::process::id()
+        ));
+        std::fs::write(&stdout, "setup\n").unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stdout_file_append(&stdout);
+        executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        executor.run(vec!["/bin/echo", "teardown"]);
+        assert_eq!(std::fs::read(&stdout).unwrap(), b"setup\nBAR\nteardown\n");
+        std::fs::remove_file(stdout).unwrap();
+    }
+
+    #[test]
+    fn run_streaming_still_captures_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_streaming();
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO; echo oops >&2"]);
+        assert_eq!(output.stdout, b"BAR\n");
+        assert_eq!(output.stderr, b"oops\n");
+    }
+
+    #[test]
+    fn truncate_output_beyond_limit() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_max_output_bytes(4);
+        let script = "yes $FOO | head -c 100000; echo oops >&2";
+        let output = executor.run(vec!["/bin/bash", "-c", script]);
+        assert_eq!(output.stdout, b"BAR\n");
+        assert_eq!(output.stderr, b"oops");
+
+        let mut exec = Execution::new(vec!["/bin/bash", "-c", "echo $FOO; exit 1"]).unwrap();
+        exec.execute(&executor.with_max_output_bytes(2)).unwrap();
+        exec.color = ColorChoice::Never;
+        assert_eq!(
+            exec.to_string(),
+            "/bin/bash -c 'echo $FOO; exit 1'\nexit status: 1\nBA\n... (stdout truncated at 2 bytes)"
+        );
+    }
+
+    #[test]
+    fn display_output_tail() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_output_tail(2)
+            .with_color(ColorChoice::Never);
+        let script = "seq 5; echo $FOO >&2; exit 1";
+        let mut exec = Execution::new(vec!["/bin/bash", "-c", script]).unwrap();
+        exec.execute(&executor).unwrap();
+        assert_eq!(exec.output().stdout, b"1\n2\n3\n4\n5\n");
+        assert_eq!(
+            exec.to_string(),
+            "/bin/bash -c 'seq 5; echo $FOO >&2; exit 1'\nexit status: 1\n\
+             ... (3 earlier lines omitted)\n4\n5\n\nBAR\n"
+        );
+    }
+
+    #[test]
+    fn outcome_counts_truncated_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_max_output_bytes(2);
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO; printf 12345 >&2"]);
+        assert_eq!(output.stdout, b"BA");
+        assert_eq!((output.stdout_len(), output.stderr_len()), (4, 5));
+        assert!(output.stdout_truncated() && output.stderr_truncated());
+
+        let output = executor.run(vec!["/bin/echo"]);
+        assert!(!output.stdout_truncated() && !output.stderr_truncated());
+
+        let output = executor.run(vec!["/bin/bash", "-c", "printf 12; printf 1"]);
+        assert!(output.stdout_truncated());
+        let output = executor.run(vec!["/bin/bash", "-c", "printf 12"]);
+        assert!(!output.stdout_truncated());
+        let output = executor.run(vec!["/bin/bash", "-c", "printf 1"]);
+        assert!(!output.stdout_truncated());
+
+        let output = executor.spawn(vec!["/bin/bash", "-c", "echo $FOO"]).wait();
+        assert_eq!((output.stdout.len(), output.stdout_len()), (2, 4));
+    }
+
+    #[test]
+    fn run_script_removes_file() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output = executor.run_script(
+            "/bin/bash",
+            "set -e\nfor word in 'a b' \"$FOO\"; do\n  echo \"$word\"\ndone\necho \"$0\" >&2\n",
+        );
+        assert_eq!(output.stdout, b"a b\nBAR\n");
+        let path = String::from_utf8(output.stderr).unwrap();
+        assert!(!Path::new(path.trim_end()).exists());
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            executor.run_script("/bin/sh", "exit 1")
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn sudo_forwards_environment() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_sudo();
+        let mut exec = Execution::new(vec!["/bin/echo", "hello"]).unwrap();
+        let command = executor.command(&mut exec);
+        assert_eq!(command.get_program(), "sudo");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-n", "--preserve-env=FOO", "--", "/bin/echo", "hello"]
+        );
+        assert!(command
+            .get_envs()
+            .any(|(key, value)| key == "FOO" && value == Some(OsStr::new("BAR"))));
+        assert_eq!(
+            executor.describe(&exec),
+            "FOO=BAR sudo -n --preserve-env=FOO -- /bin/echo hello"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Command requires passwordless sudo")]
+    fn sudo_reports_password_required() {
+        let dir =
+            std::env::temp_dir().join(format!("test_process_executor-{}-sudo", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sudo = dir.join("sudo");
+        std::fs::write(
+            &sudo,
+            "#!/bin/sh\necho 'sudo: a password is required' >&2\nexit 1\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&sudo, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        Executor::new(vec![("FOO", "BAR")])
+            .with_env("PATH", dir.to_str().unwrap())
+            .with_sudo()
+            .run(vec!["/bin/echo", "hello"]);
+    }
+
+    #[test]
+    fn run_expecting_created_file() {
+        let dir = std::env::temp_dir().join(format!(
+            "test_process_executor-{}-created",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_current_dir(&dir);
+        executor.run_expecting_file(vec!["/bin/bash", "-c", "echo $FOO > out"], "out");
+        executor.run_expecting_file_contents(vec!["/bin/true"], dir.join("out"), "BAR\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "test_process_executor-missing to exist; contents of")]
+    fn run_expecting_missing_file() {
+        let path = std::env::temp_dir().join("test_process_executor-missing");
+        Executor::new(vec![("FOO", "BAR")]).run_expecting_file(vec!["/bin/true"], path);
+    }
+
+    #[test]
+    fn seed_is_provided() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_seed("SEED", 42);
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO $SEED"]);
+        assert_eq!(output.stdout, b"BAR 42\n");
+
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_random_seed("SEED");
+        let first = executor.run(vec!["/bin/bash", "-c", "echo $SEED"]).stdout;
+        let second = executor.run(vec!["/bin/bash", "-c", "echo $SEED"]).stdout;
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    #[should_panic(expected = "exit status: 1\nBAR\nSeed: SEED=7")]
+    fn seed_is_shown_on_failure() {
+        Executor::new(vec![("FOO", "BAR")])
+            .with_color(ColorChoice::Never)
+            .with_seed("SEED", 7)
+            .run(vec!["/bin/bash", "-c", "echo $FOO; exit 1"]);
+    }
+
+    #[test]
+    fn convert_error() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let script = "echo $FOO; for i in $(seq 30); do echo $i >&2; done; exit 1";
+        let err = executor
+            .try_run(vec!["/bin/bash", "-c", script])
+            .unwrap_err()
+            .into_io_error();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        let msg = err.to_string();
+        assert!(msg.starts_with("/bin/bash -c "), "{}", msg);
+        assert!(
+            msg.contains("BAR\n\n... (10 earlier lines omitted)\n11\n"),
+            "{}",
+            msg
+        );
+        assert!(msg.ends_with("29\n30\n"), "{}", msg);
+
+        let run = || -> anyhow::Result<RunOutcome> { Ok(executor.try_run(vec!["/bin/false"])?) };
+        let err = run().unwrap_err();
+        assert!(err.downcast_ref::<ExecutionError>().is_some());
+    }
+
+    #[test]
+    fn run_expecting_same_stdout_of_commands() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let (a, b) = executor.run_expecting_same_stdout(
+            vec!["/bin/bash", "-c", "echo $FOO"],
+            vec!["/bin/echo", "BAR"],
+        );
+        assert_eq!((a.stdout, b.stdout), (b"BAR\n".to_vec(), b"BAR\n".to_vec()));
+        executor.run_expecting_same_stdout_normalized(
+            vec!["/bin/bash", "-c", "echo \"$FOO  \""],
+            vec!["/bin/echo", "BAR"],
+            |stdout| stdout.lines().map(|line| line.trim_end()).collect(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "a: /bin/echo BAR\nb: /bin/echo BAZ\n--- a\n+++ b\n")]
+    fn run_expecting_same_stdout_reports_diff() {
+        Executor::new(vec![("FOO", "BAR")])
+            .run_expecting_same_stdout(vec!["/bin/echo", "BAR"], vec!["/bin/echo", "BAZ"]);
+    }
+
+    #[test]
+    fn run_with_ci_grouping() {
+        for flavor in [CiFlavor::Auto, CiFlavor::GitHub, CiFlavor::GitLab] {
+            let output = Executor::new(vec![("FOO", "BAR")])
+                .with_ci_grouping(flavor)
+                .run(vec!["/bin/bash", "-c", "echo $FOO"]);
+            assert_eq!(output.stdout, b"BAR\n");
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn run_with_transcript() {
+        let path = std::env::temp_dir().join(format!(
+            "test_process_executor-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_transcript(&path);
+        executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        executor.run_expecting_code(vec!["/bin/bash", "-c", "echo oops >&2; exit 3"], 3);
+        let transcript = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<serde_json::Value> = transcript
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["command"][2], "echo $FOO");
+        assert_eq!(records[0]["env"], serde_json::json!([["FOO", "***"]]));
+        assert_eq!(records[0]["stdout"], "BAR\n");
+        assert_eq!(records[1]["code"], 3);
+        assert_eq!(records[1]["stderr"], "oops\n");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn transcript_records_launched_env_and_pipe_stages() {
+        let path = std::env::temp_dir().join(format!(
+            "test_process_executor-{}.stages.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_env_lazy(move || {
+                let n = counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                ("N".into(), n.to_string().into())
+            })
+            .with_debug_show_values()
+            .with_transcript(&path);
+        let output = executor.pipe(vec![vec!["/bin/bash", "-c", "echo $N"], vec!["/bin/cat"]]);
+        let transcript = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<serde_json::Value> = transcript
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0]["env"][1],
+            serde_json::json!(["N", output.stdout_trimmed()])
+        );
+        assert_eq!(records[1]["command"][0], "/bin/cat");
+    }
+
+    #[test]
+    fn run_expecting_duration_in_range() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output = executor.run_expecting_duration(
+            vec!["/bin/bash", "-c", "sleep 0.1; echo $FOO"],
+            Duration::from_millis(50),
+            Duration::from_secs(30),
+        );
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Completed too quickly: took")]
+    fn run_expecting_duration_too_fast() {
+        Executor::new(vec![("FOO", "BAR")]).run_expecting_duration(
+            vec!["/bin/true"],
+            Duration::from_secs(10),
+            Duration::from_secs(20),
+        );
+    }
+
+    #[test]
+    fn try_run_expecting_duration_reports_errors() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let err = executor
+            .try_run_expecting_duration(
+                vec!["/bin/true"],
+                Duration::from_secs(10),
+                Duration::from_secs(20),
+            )
+            .unwrap_err();
+        assert!(matches!(err, ExecutionError::Unexpected { .. }));
+        assert!(err.to_string().contains("Completed too quickly"));
+        let err = executor
+            .try_run_expecting_duration(
+                vec!["/bin/true"],
+                Duration::from_secs(2),
+                Duration::from_secs(1),
+            )
+            .unwrap_err();
+        assert!(matches!(err, ExecutionError::Failed { .. }));
+    }
+
+    #[test]
+    fn run_with_specific_shell() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let script = "echo $FOO ${BASH_VERSION:+bash}";
+        assert_eq!(
+            executor.run_with_shell("/bin/bash", script).stdout,
+            b"BAR bash\n"
+        );
+        let output = executor.run_with_shell("/bin/sh", "echo $FOO; exit 0");
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    fn executor_is_shareable() {
+        fn shareable<T: Clone + Send + Sync>(_: &T) {}
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        shareable(&executor);
+        let specialized = executor.clone().with_env("FOO", "BAZ");
+        let run = |executor: &Executor<_, _>| executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        assert_eq!(run(&specialized).stdout, b"BAZ\n");
+        assert_eq!(run(&executor).stdout, b"BAR\n");
+    }
+
+    #[test]
+    fn assert_running_process_alive() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let mut process = executor.spawn(vec!["/bin/sleep", "10"]);
+        process.assert_alive_after(Duration::from_millis(50));
+        process.kill();
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected process to be running after 500ms\n\
+                               /bin/bash -c 'echo $FOO; exit 2'\nexit status: 2\nBAR\n")]
+    fn assert_exited_process_alive() {
+        Executor::new(vec![("FOO", "BAR")])
+            .with_color(ColorChoice::Never)
+            .spawn(vec!["/bin/bash", "-c", "echo $FOO; exit 2"])
+            .assert_alive_after(Duration::from_millis(500));
+    }
+
+    #[test]
+    fn env_prefix_applies_to_later_variables() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_env_prefix("MYAPP_")
+            .with_env("PORT", "8080")
+            .with_env_prefix("")
+            .with_env("HOST", "localhost");
+        let output = executor.run(vec![
+            "/bin/bash",
+            "-c",
+            "echo $FOO $MYAPP_PORT ${PORT:-unset} $HOST",
+        ]);
+        assert_eq!(output.stdout, b"BAR 8080 unset localhost\n");
+    }
+
+    #[test]
+    fn env_prefix_applies_in_call_order() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_env_prefix("MYAPP_")
+            .with_env("PORT", "8080")
+            .with_env_prefix("")
+            .with_env("MYAPP_PORT", "9090");
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO $MYAPP_PORT"]);
+        assert_eq!(output.stdout, b"BAR 9090\n");
+    }
+
+    #[test]
+    fn run_until_condition_succeeds() {
+        let path =
+            std::env::temp_dir().join(format!("test_process_executor-{}.poll", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_env("POLL", path.to_str().unwrap());
+        let script = "echo >> $POLL; [ $(wc -l < $POLL) -ge 3 ] && echo $FOO";
+        let output = executor.run_until_success(
+            vec!["/bin/bash", "-c", script],
+            Duration::from_millis(10),
+            Duration::from_secs(30),
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Did not succeed within 100ms, after")]
+    fn run_until_success_times_out() {
+        Executor::new(vec![("FOO", "BAR")]).run_until_success(
+            vec!["/bin/false"],
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn run_expecting_output_matches() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let script = "echo pid $$; echo $FOO; echo took 12ms >&2";
+        executor
+            .run_expecting_stdout_matches(vec!["/bin/bash", "-c", script], r"(?m)^pid \d+\nBAR$");
+        executor.run_expecting_stderr_matches(vec!["/bin/bash", "-c", script], r"took \d+ms");
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    #[should_panic(expected = "Expected stdout to match /^BAZ/\n/bin/bash -c 'echo $FOO'\nBAR\n")]
+    fn run_expecting_stdout_regex_mismatch() {
+        Executor::new(vec![("FOO", "BAR")])
+            .with_color(ColorChoice::Never)
+            .run_expecting_stdout_matches(vec!["/bin/bash", "-c", "echo $FOO"], "^BAZ");
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    #[should_panic(expected = "Invalid pattern \"(\"")]
+    fn run_expecting_stdout_matches_invalid_pattern() {
+        Executor::new(vec![("FOO", "BAR")])
+            .with_pre_run_hook(|_| panic!("launched"))
+            .run_expecting_stdout_matches(vec!["/bin/true"], "(");
+    }
+
+    #[test]
+    fn process_runner_can_be_mocked() {
+        fn greeting(runner: &dyn ProcessRunner) -> String {
+            let args = ["/bin/bash", "-c", "echo $FOO"].map(OsStr::new);
+            runner.run(&args).stdout_trimmed()
+        }
+        assert_eq!(greeting(&Executor::new(vec![("FOO", "BAR")])), "BAR");
+
+        let mock = MockRunner::new()
+            .with_stdout("BAZ\n")
+            .with_exit_code(2, "oops");
+        assert_eq!(greeting(&mock), "BAZ");
+        let err = mock.try_run(&[OsStr::new("/bin/false")]).unwrap_err();
+        assert!(
+            matches!(err, ExecutionError::NonZeroExit { ref stderr, .. } if stderr == b"oops"),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            mock.invocations(),
+            vec![
+                vec![
+                    OsString::from("/bin/bash"),
+                    OsString::from("-c"),
+                    OsString::from("echo $FOO")
+                ],
+                vec![OsString::from("/bin/false")],
+            ]
+        );
+    }
+
+    #[test]
+    fn path_args_are_canonicalized() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_current_dir(std::env::temp_dir())
+            .with_path_arg_canonicalization();
+        let output = executor.run(vec!["/bin/echo", "src/lib.rs", "missing", "$FOO"]);
+        let expected = format!(
+            "{} missing $FOO\n",
+            Path::new("src/lib.rs").canonicalize().unwrap().display()
+        );
+        assert_eq!(output.stdout_str(), expected);
+    }
+
+    #[test]
+    fn stats_count_launches() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stats();
+        assert_eq!(executor.stats(), ExecStats::default());
+        executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        executor.clone().run_expecting_failure(vec!["/bin/false"]);
+        executor
+            .clone()
+            .with_timeout(Duration::from_millis(50))
+            .with_failure_strategy(FailureStrategy::Return)
+            .run(vec!["/bin/sleep", "10"]);
+        let stats = executor.stats();
+        assert_eq!((stats.launches, stats.failures, stats.timeouts), (3, 2, 1));
+        assert!(stats.longest >= Duration::from_millis(50));
+        assert!(stats.total_time >= stats.longest);
+        assert!(stats
+            .to_string()
+            .starts_with("3 launches, 2 failed (1 timed out), in "));
+    }
+
+    #[test]
+    fn stats_count_pipeline_stages_and_spawn_failures() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stats();
+        executor.pipe(vec![vec!["/bin/bash", "-c", "echo $FOO"], vec!["/bin/cat"]]);
+        assert!(executor.try_run(vec!["/nonexistent"]).is_err());
+        let stats = executor.stats();
+        assert_eq!((stats.launches, stats.failures, stats.timeouts), (3, 1, 0));
+    }
+
+    #[test]
+    fn chroot_requires_program_within_root() {
+        let root =
+            std::env::temp_dir().join(format!("test_process_executor-{}-root", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_chroot(&root);
+        let err = executor.try_run(vec!["/bin/true"]).unwrap_err();
+        std::fs::remove_dir_all(&root).unwrap();
+        // Depending on privileges, either the root cannot be changed, or
+        // the program is missing within it.
+        let msg = err.to_string();
+        assert!(matches!(err, ExecutionError::SpawnFailed { .. }), "{}", msg);
+        assert!(
+            msg.contains("typically requires root") || msg.contains("must exist within the root"),
+            "{}",
+            msg
+        );
+    }
+
+    #[test]
+    fn run_expecting_idempotent_command() {
+        let path = std::env::temp_dir().join(format!(
+            "test_process_executor-{}.idempotent",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let executor =
+            Executor::new(vec![("FOO", "BAR")]).with_env("STATE", path.to_str().unwrap());
+        let script =
+            "if [ -e $STATE ]; then echo unchanged; else echo $FOO > $STATE; echo created; fi";
+        let (first, second) = executor
+            .run_expecting_idempotent_with_stdout(vec!["/bin/bash", "-c", script], "unchanged\n");
+        assert_eq!(
+            (first.stdout, second.stdout),
+            (b"created\n".to_vec(), b"unchanged\n".to_vec())
+        );
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            executor.run_expecting_idempotent(vec!["/bin/bash", "-c", "rm $STATE"])
+        }));
+        assert!(!path.exists());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Second run failed:\n/bin/bash -c")]
+    fn run_expecting_idempotent_reports_second_run() {
+        let path =
+            std::env::temp_dir().join(format!("test_process_executor-{}.once", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Executor::new(vec![("FOO", "BAR")])
+            .with_env("STATE", path.to_str().unwrap())
+            .run_expecting_idempotent(vec![
+                "/bin/bash",
+                "-c",
+                "set -o noclobber; echo $FOO > $STATE",
+            ]);
+    }
+
+    #[test]
+    fn try_run_expecting_same_stdout_and_idempotent() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let (a, b) = executor
+            .try_run_expecting_same_stdout(
+                vec!["/bin/bash", "-c", "echo $FOO"],
+                vec!["/bin/bash", "-c", "echo BAR"],
+            )
+            .unwrap();
+        assert_eq!(a.stdout, b.stdout);
+        let err = executor
+            .try_run_expecting_same_stdout(
+                vec!["/bin/bash", "-c", "echo $FOO"],
+                vec!["/bin/bash", "-c", "echo BAZ"],
+            )
+            .unwrap_err();
+        assert!(matches!(err, ExecutionError::Failed { .. }));
+        let err = executor
+            .try_run_expecting_idempotent_with_stdout(vec!["/bin/bash", "-c", "echo $FOO"], "BAZ\n")
+            .unwrap_err();
+        assert!(matches!(err, ExecutionError::Unexpected { .. }));
+        assert!(executor
+            .try_run_expecting_idempotent(vec!["/bin/false"])
+            .is_err());
+    }
+
+    #[test]
+    fn normalize_newlines_in_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_normalize_newlines();
+        let output = executor.run(vec![
+            "/bin/bash",
+            "-c",
+            "printf \"$FOO\\r\\nBAZ\\rQUX\\n\"; printf 'a\\r\\n' >&2",
+        ]);
+        assert_eq!(output.stdout, b"BAR\nBAZ\nQUX\n");
+        assert_eq!(output.raw_stdout(), b"BAR\r\nBAZ\rQUX\n");
+        assert_eq!(
+            (&output.stderr[..], output.raw_stderr()),
+            (&b"a\n"[..], &b"a\r\n"[..])
+        );
+        assert!(!output.stdout_truncated());
+
+        executor.run_expecting_stdout_eq(vec!["/bin/bash", "-c", "printf 'X\\r\\n'"], "X\n");
+        let output =
+            Executor::new(vec![("FOO", "BAR")]).run(vec!["/bin/bash", "-c", "printf 'X\\r\\n'"]);
+        assert_eq!(output.stdout, output.raw_stdout());
+    }
+
+    #[test]
+    fn run_with_default_executor() {
+        let output = run(vec!["/bin/echo", "hello"]);
+        assert_eq!(output.stdout, b"hello\n");
+    }
+
+    #[test]
+    fn command_line_quotes_arguments() {
+        assert_eq!(
+            command_line(vec!["/bin/bash", "-c", "echo $FOO", ""]),
+            "/bin/bash -c 'echo $FOO' ''"
+        );
+        assert_eq!(command_line(Vec::<&str>::new()), "");
+    }
+
+    #[test]
+    fn run_with_inherited_stdio() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_inherited_stdio()
+            .with_color(ColorChoice::Never);
+        let output = executor.run(vec!["/bin/bash", "-c", "[ \"$FOO\" == \"BAR\" ]"]);
+        assert!(output.stdout.is_empty());
+
+        let mut exec = Execution::new(vec!["/bin/bash", "-c", "exit 1"]).unwrap();
+        exec.execute(&executor).unwrap();
+        assert_eq!(
+            exec.to_string(),
+            "/bin/bash -c 'exit 1'\nexit status: 1\n(stdio inherited; output not captured)"
+        );
+    }
+
+    #[test]
+    fn run_with_passthrough() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_passthrough()
+            .with_stdin("input");
+        let output = executor.run(vec!["/bin/bash", "-c", "read line; echo $FOO $line"]);
+        assert!(output.stdout.is_empty());
+        assert!(output.stderr.is_empty());
+        assert!(executor.try_run(vec!["/bin/bash", "-c", "exit 1"]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "pty")]
+    fn run_with_pty() {
+        let script = "[ -t 1 ] && echo $FOO; [ -t 2 ] && echo oops >&2; stty size </dev/tty; true";
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        // The caller may itself have a controlling terminal.
+        let output = executor.run(vec!["/bin/bash", "-c", script]);
+        assert!(!output.stdout_str().contains("BAR"));
+        let output = executor.with_pty().run(vec!["/bin/bash", "-c", script]);
+        assert_eq!(output.stdout, b"BAR\r\noops\r\n24 80\r\n");
+    }
+
+    #[test]
+    fn run_with_merged_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_merged_output();
+        let script = "echo $FOO; echo oops >&2; echo baz";
+        let output = executor.run(vec!["/bin/bash", "-c", script]);
+        assert_eq!(output.stdout, b"BAR\noops\nbaz\n");
+        assert!(output.stderr.is_empty());
+        let process = executor.spawn(vec!["/bin/bash", "-c", script]);
+        assert_eq!(process.wait().stdout, b"BAR\noops\nbaz\n");
+    }
+
+    #[test]
+    fn display_quotes_arguments() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let err = executor
+            .try_run(vec!["/bin/bash", "-c", "echo \"$FOO's\"; exit 1"])
+            .unwrap_err();
+        assert!(
+            err.styled(false)
+                .to_string()
+                .starts_with(r#"/bin/bash -c 'echo "$FOO'\''s"; exit 1'"#),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn display_with_color_choice() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let err = executor
+            .try_run(vec!["/bin/bash", "-c", "echo $FOO; exit 1"])
+            .unwrap_err();
+        assert!(err
+            .styled(true)
+            .to_string()
+            .contains("\x1b[92mBAR\n\x1b[0m"));
+        assert!(!err.styled(false).to_string().contains('\x1b'));
+    }
+
+    #[test]
+    #[should_panic(expected = "/bin/false\nexit status: 1")]
+    fn run_without_color() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_color(ColorChoice::Never);
+        executor.run(vec!["/bin/false"]);
+    }
+
+    #[test]
+    fn run_expecting_output_contains() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let args = vec!["/bin/bash", "-c", "echo foo $FOO baz; echo oops >&2"];
+        executor.run_expecting_stdout_contains(&args, "BAR");
+        executor.run_expecting_stdout_contains(&args, "");
+        executor.run_expecting_stderr_contains(&args, "oops");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected stdout to contain \"BAZINGA\"")]
+    fn run_expecting_stdout_missing() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_stdout_contains(vec!["/bin/bash", "-c", "echo $FOO"], "BAZINGA");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected stderr to contain \"BAR\"")]
+    fn run_expecting_stderr_missing() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_stderr_contains(vec!["/bin/bash", "-c", "echo $FOO"], "BAR");
+    }
+
+    #[test]
+    fn run_expecting_exact_stdout() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_stdout_eq(vec!["/bin/bash", "-c", "echo $FOO"], "BAR\n");
+        executor.run_expecting_stdout_eq(vec!["/bin/bash", "-c", "printf '\\xff'"], [0xff]);
+        executor.run_expecting_stdout_lines(
+            vec!["/bin/bash", "-c", "echo $FOO; printf baz"],
+            ["BAR", "baz"],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected stdout to equal \"BAR\", but got \"BAR\\n\"")]
+    fn run_expecting_stdout_mismatch() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_stdout_eq(vec!["/bin/bash", "-c", "echo $FOO"], "BAR");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected stdout line 2 to be Some(\"baz\"), but got None")]
+    fn run_expecting_stdout_lines_mismatch() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_stdout_lines(vec!["/bin/bash", "-c", "echo $FOO"], ["BAR", "baz"]);
+    }
+
+    #[test]
+    fn run_matching_golden_file() {
+        let path = std::env::temp_dir().join(format!(
+            "test_process_executor-{}.golden",
+            std::process::id()
+        ));
+        std::fs::write(&path, "BAR\nBAZ\n").unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            executor.run_matching_file(vec!["/bin/bash", "-c", "echo $FOO; echo BAZ"], &path);
+            executor.run_matching_file(vec!["/bin/bash", "-c", "echo $FOO; echo QUX"], &path)
+        }));
+        std::fs::remove_file(&path).unwrap();
+        let err = result.unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(
+            msg.contains("@@ -1,2 +1,2 @@\n BAR\n-BAZ\n+QUX\n"),
+            "{}",
+            msg
+        );
+    }
+
+    #[test]
+    fn run_expecting_line_count() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_line_count(vec!["/bin/bash", "-c", "echo $FOO; echo; printf x"], 3);
+        let err = executor
+            .try_run_expecting_line_count(vec!["/bin/bash", "-c", "echo $FOO"], 2)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Expected 2 lines of stdout, but got 1\n"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn run_expecting_clean_stderr() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output = executor.run_expecting_clean_stderr(vec!["/bin/bash", "-c", "echo $FOO"]);
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected stderr to be empty")]
+    fn run_expecting_clean_stderr_with_warning() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_expecting_clean_stderr(vec!["/bin/bash", "-c", "echo warning >&2"]);
+    }
+
+    #[test]
+    fn strict_stderr_rejects_warnings() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_strict_stderr();
+        executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        let err = executor
+            .try_run(vec!["/bin/bash", "-c", "echo warning: $FOO >&2"])
+            .unwrap_err();
+        assert!(
+            matches!(&err, ExecutionError::Unexpected { reason, .. }
+                if reason == "Expected no output to stderr, but got \"warning: BAR\""),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn strict_stderr_permits_allowed_lines() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stderr_allow(["deprecated"]);
+        executor.run(vec!["/bin/bash", "-c", "echo option is deprecated >&2"]);
+        assert!(executor
+            .try_run(vec![
+                "/bin/bash",
+                "-c",
+                "echo deprecated >&2; echo $FOO >&2"
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn run_asserting_predicate() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output = executor.run_asserting(vec!["/bin/bash", "-c", "echo $FOO"], |output| {
+            output.stdout.starts_with(b"BAR")
+        });
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Output failed check: predicate returned false\n/bin/bash")]
+    fn run_asserting_unsatisfied_predicate() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_asserting(vec!["/bin/bash", "-c", "echo $FOO"], |output| {
+            output.stdout.is_empty()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Output failed check: expected 2 lines, got 1")]
+    fn run_checking_reports_error() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_checking(vec!["/bin/bash", "-c", "echo $FOO"], |output| match output
+            .stdout
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count()
+        {
+            2 => Ok(()),
+            n => Err(format!("expected 2 lines, got {}", n)),
+        });
+    }
+
+    #[test]
+    fn run_timed_measures_duration() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let elapsed = executor.run_timed(vec!["/bin/sleep", "0.2"]);
+        assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn dry_run_skips_execution() {
+        let dir = std::env::temp_dir();
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_current_dir(&dir)
+            .dry_run();
+        let mut exec = Execution::new(vec!["/bin/false", "arg"]).unwrap();
+        exec.color = ColorChoice::Never;
+        assert_eq!(
+            executor.describe(&exec),
+            format!("cd {} && FOO=BAR /bin/false arg", dir.display())
+        );
+        let output = executor.run(vec!["/bin/false"]);
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+        executor.run_expecting_code(vec!["/bin/false"], 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "[database setup] Expected exit code 0, but got 1\n/bin/false")]
+    fn context_prefixes_failures() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_context("database setup")
+            .with_color(ColorChoice::Never);
+        executor.run_expecting_code(vec!["/bin/false"], 0);
+    }
+
+    #[test]
+    fn failure_strategy_returns_failed_output() {
+        let executor =
+            Executor::new(vec![("FOO", "BAR")]).with_failure_strategy(FailureStrategy::Return);
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO; exit 2"]);
+        assert_eq!(output.code(), Some(2));
+        assert_eq!(output.stdout, b"BAR\n");
+        let output = executor.run_expecting_code(vec!["/bin/false"], 0);
+        assert_eq!(output.code(), Some(1));
+    }
+
+    #[test]
+    fn failure_strategy_calls_custom_handler() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_failure_strategy(
+            FailureStrategy::Custom(Arc::new({
+                let reported = reported.clone();
+                move |err| reported.lock().unwrap().push(err)
+            })),
+        );
+        let fails = |f: &dyn Fn()| {
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_err();
+        };
+        fails(&|| drop(executor.run(vec!["/bin/bash", "-c", "echo $FOO; exit 2"])));
+        fails(&|| drop(executor.run(Vec::<&str>::new())));
+        fails(&|| drop(executor.run_parallel(vec![vec!["/bin/false"]])));
+        let reported = reported.lock().unwrap();
+        assert!(matches!(
+            &reported[..],
+            [
+                ExecutionError::NonZeroExit { stdout, .. },
+                ExecutionError::EmptyArgs,
+                ExecutionError::Failed { message },
+            ] if stdout == b"BAR\n" && message.starts_with("1 of 1 commands failed")
+        ));
+    }
+
+    #[test]
+    fn verbose_still_runs_command() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).verbose(true);
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $FOO"]);
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    fn retry_until_success() {
+        let dir = std::env::temp_dir();
+        let marker = dir.join(format!(
+            "test_process_executor-{}.retry",
+            std::process::id()
+        ));
+        let script = "if [ -e \"$1\" ]; then exit 0; else touch \"$1\"; exit 1; fi";
+        let executor =
+            Executor::new(vec![("FOO", "BAR")]).with_retries(1, Duration::from_millis(10));
+        executor.run(vec![
+            "/bin/bash",
+            "-c",
+            script,
+            "bash",
+            marker.to_str().unwrap(),
+        ]);
+        std::fs::remove_file(marker).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "(failed after 3 attempts)")]
+    fn retry_until_exhausted() {
+        let executor =
+            Executor::new(vec![("FOO", "BAR")]).with_retries(2, Duration::from_millis(10));
+        executor.run(vec!["/bin/false"]);
+    }
+
+    #[test]
+    fn timeout_bounds_output_held_open() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_timeout(Duration::from_millis(300));
+        let started = Instant::now();
+        let result = executor.try_run(vec!["/bin/bash", "-c", "echo $FOO; sleep 5 &"]);
+        assert!(started.elapsed() < Duration::from_secs(3));
+        match result {
+            Err(ExecutionError::TimedOut { stdout, .. }) => assert_eq!(stdout, b"BAR\n"),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn run_all_in_sequence() {
+        let dir = std::env::temp_dir();
+        let marker = dir.join(format!(
+            "test_process_executor-{}.run_all",
+            std::process::id()
+        ));
+        let marker = marker.to_str().unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_all(vec![
+            vec!["/usr/bin/touch", marker],
+            vec!["/usr/bin/test", "-e", marker],
+            vec!["/bin/rm", marker],
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Command at index 1 failed:")]
+    fn run_all_stops_at_failure() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_all(vec![
+            vec!["/bin/true"],
+            vec!["/bin/false"],
+            vec!["/bin/bash", "-c", "echo unreachable; exit 1"],
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Command at index 1 failed:")]
+    fn run_all_stops_at_failure_under_return_strategy() {
+        let executor =
+            Executor::new(vec![("FOO", "BAR")]).with_failure_strategy(FailureStrategy::Return);
+        executor.run_all(vec![
+            vec!["/bin/true"],
+            vec!["/bin/false"],
+            vec!["/bin/bash", "-c", "echo unreachable; exit 1"],
+        ]);
+    }
+
+    #[test]
+    fn run_all_collect_reports_every_failure() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let failures = executor
+            .run_all_collect(vec![
+                vec!["/bin/false"],
+                vec!["/bin/true"],
+                vec!["/bin/bash", "-c", "echo $FOO; exit 2"],
+            ])
+            .unwrap_err();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].index, 0);
+        assert_eq!(failures[1].index, 2);
+        match &failures[1].error {
+            ExecutionError::NonZeroExit { status, stdout, .. } => {
+                assert_eq!(status.code(), Some(2));
+                assert_eq!(stdout, b"BAR\n");
+            }
+            other => panic!("Unexpected error: {:?}", other),
+        }
+        assert!(executor
+            .run_all_collect(vec![vec!["/bin/true"], vec!["/bin/true"]])
+            .is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "Exceeded total deadline of 300ms at command index 2")]
+    fn run_all_exceeds_total_deadline() {
+        let executor =
+            Executor::new(vec![("FOO", "BAR")]).with_total_deadline(Duration::from_millis(300));
+        executor.run_all(vec![
+            vec!["/bin/sleep", "0.1"],
+            vec!["/bin/sleep", "0.1"],
+            vec!["/bin/sleep", "10"],
+        ]);
+    }
+
+    #[test]
+    fn run_all_total_deadline_bounds_retries() {
+        let executor = Executor::new(vec![("FOO", "BAR")])
+            .with_retries(3, Duration::from_millis(10))
+            .with_total_deadline(Duration::from_millis(300));
+        let started = Instant::now();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            executor.run_all(vec![vec!["/bin/sleep", "10"]])
+        }));
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_millis(600));
+    }
+
+    #[test]
+    fn run_parallel_joins_outputs() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let started = Instant::now();
+        let outputs = executor.run_parallel(vec![
+            vec!["/bin/bash", "-c", "sleep 0.5; echo $FOO"],
+            vec!["/bin/bash", "-c", "sleep 0.5; echo BAZ"],
+        ]);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(outputs[0].stdout, b"BAR\n");
+        assert_eq!(outputs[1].stdout, b"BAZ\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "2 of 3 commands failed:\nCommand at index 0 failed")]
+    fn run_parallel_reports_every_failure() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run_parallel(vec![
+            vec!["/bin/false"],
+            vec!["/bin/true"],
+            vec!["/bin/false"],
+        ]);
+    }
+
+    #[test]
+    fn run_matrix_across_environments() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let outputs = executor.run_matrix(
+            vec!["/bin/bash", "-c", "echo $FOO $LANG"],
+            vec![
+                vec![("LANG", "C")],
+                vec![("LANG", "en_US.UTF-8"), ("FOO", "BAZ")],
+            ],
+        );
+        assert_eq!(outputs[0].stdout, b"BAR C\n");
+        assert_eq!(outputs[1].stdout, b"BAZ en_US.UTF-8\n");
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "1 of 3 environments failed:\nEnvironment at index 1 (MODE=bad 'LANG=en US') failed:\n/bin/bash"
+    )]
+    fn run_matrix_reports_failing_environments() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_color(ColorChoice::Never);
+        executor.run_matrix(
+            vec!["/bin/bash", "-c", "[ \"$MODE\" != bad ]"],
+            vec![
+                vec![("MODE", "good")],
+                vec![("MODE", "bad"), ("LANG", "en US")],
+                vec![],
+            ],
+        );
+    }
+
+    #[test]
+    fn pipe_between_commands() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_stdin("b\nc\na\n");
+        let output = executor.pipe(vec![
+            vec!["/usr/bin/sort", "-r"],
+            vec!["/bin/bash", "-c", "cat; echo $FOO"],
+            vec!["/usr/bin/tr", "a-z", "A-Z"],
+        ]);
+        assert_eq!(output.stdout, b"C\nB\nA\nBAR\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Pipeline stage 1 failed:\n/bin/bash -c 'cat >/dev/null; exit 2'")]
+    fn pipe_reports_failing_stage() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_color(ColorChoice::Never);
+        executor.pipe(vec![
+            vec!["/bin/bash", "-c", "echo $FOO"],
+            vec!["/bin/bash", "-c", "cat >/dev/null; exit 2"],
+            vec!["/bin/cat"],
+        ]);
+    }
+
+    #[test]
+    fn return_strategy_covers_pipes_and_paired_helpers() {
+        let executor =
+            Executor::new(vec![("FOO", "BAR")]).with_failure_strategy(FailureStrategy::Return);
+        let output = executor.pipe(vec![
+            vec!["/bin/bash", "-c", "echo $FOO"],
+            vec!["/bin/bash", "-c", "cat >/dev/null; exit 2"],
+            vec!["/bin/bash", "-c", "cat; echo $FOO"],
+        ]);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"BAR\n");
+        let (a, b) = executor.run_expecting_same_stdout(
+            vec!["/bin/bash", "-c", "echo $FOO"],
+            vec!["/bin/bash", "-c", "echo BAZ"],
+        );
+        assert_ne!(a.stdout, b.stdout);
+        let (_, second) = executor
+            .run_expecting_idempotent_with_stdout(vec!["/bin/bash", "-c", "echo $FOO"], "BAZ\n");
+        assert_eq!(second.stdout, b"BAR\n");
+    }
+
+    #[test]
+    fn build_command_applies_configuration() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_current_dir(&dir);
+        let output = executor
+            .build_command(vec!["/bin/bash", "-c", "echo $FOO; pwd -P"])
+            .output()
+            .unwrap();
+        assert_eq!(
+            output.stdout,
+            format!("BAR\n{}\n", dir.display()).into_bytes()
+        );
+    }
+
+    #[test]
+    fn run_tagged_records_stream_of_each_line() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let script = "echo $FOO; sleep 0.1; echo warning >&2; sleep 0.1; printf 'a\\r\\nb'";
+        assert_eq!(
+            executor.run_tagged(vec!["/bin/bash", "-c", script]),
+            vec![
+                (Stream::Stdout, "BAR".to_string()),
+                (Stream::Stderr, "warning".to_string()),
+                (Stream::Stdout, "a".to_string()),
+                (Stream::Stdout, "b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn spawn_and_wait() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let process = executor.spawn(vec!["/bin/bash", "-c", "echo $FOO"]);
+        let output = process.wait();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"BAR\n");
+    }
+
+    #[test]
+    fn spawn_and_read_lines() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let mut process = executor.spawn(vec![
+            "/bin/bash",
+            "-c",
+            "echo starting; echo $FOO; exec sleep 10",
+        ]);
+        let lines: Vec<String> = process
+            .stdout_lines()
+            .take(2)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(lines, vec!["starting", "BAR"]);
+        process.kill();
+        assert_eq!(process.wait().stdout, b"starting\nBAR\n");
+    }
+
+    #[test]
+    fn spawn_and_kill() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let mut process = executor.spawn(vec!["/bin/sleep", "10"]);
+        assert_ne!(process.pid(), 0);
+        process.kill();
+        assert!(!process.wait().status.success());
+    }
+
+    #[test]
+    fn spawn_and_wait_with_timeout() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let mut process = executor.spawn(vec!["/bin/sleep", "10"]);
+        assert_eq!(process.wait_timeout(Duration::from_millis(50)), None);
+        process.kill();
+
+        let mut process = executor.spawn(vec!["/bin/bash", "-c", "echo $FOO; exit 3"]);
+        let status = process.wait_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(process.wait().stdout, b"BAR\n");
+    }
+
+    /// Returns a port on which nothing is listening.
+    fn unused_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[test]
+    fn spawn_and_wait_for_port_polls_until_listening() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let port = unused_port();
+        // Stands in for a server which takes a while to start listening.
+        let listener = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            std::net::TcpListener::bind(("127.0.0.1", port)).unwrap()
+        });
+        let started = Instant::now();
+        let process = executor.spawn_and_wait_for_port(
+            vec!["/bin/sleep", "10"],
+            port,
+            Duration::from_secs(10),
+        );
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        drop(process);
+        listener.join().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "accept connections\nTimed out after 100ms\n/bin/bash")]
+    fn spawn_and_wait_for_port_times_out() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_color(ColorChoice::Never);
+        executor.spawn_and_wait_for_port(
+            vec!["/bin/bash", "-c", "echo $FOO; sleep 10"],
+            unused_port(),
+            Duration::from_millis(100),
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "accepted connections\n/bin/bash -c 'echo $FOO; exit 3'\nexit status: 3\nBAR"
+    )]
+    fn spawn_and_wait_for_port_reports_early_exit() {
+        let executor = Executor::new(vec![("FOO", "BAR")]).with_color(ColorChoice::Never);
+        executor.spawn_and_wait_for_port(
+            vec!["/bin/bash", "-c", "echo $FOO; exit 3"],
+            unused_port(),
+            Duration::from_secs(10),
+        );
+    }
+
+    #[test]
+    fn spawned_process_killed_on_drop() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let process = executor.spawn(vec!["/bin/sleep", "10"]);
+        let pid = process.pid().to_string();
+        drop(process);
+        executor.run_expecting_code(vec!["/bin/kill", "-0", &pid], 1);
+    }
+
+    #[test]
+    fn leaked_process_outlives_handle() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let pid = executor.spawn(vec!["/bin/sleep", "10"]).leak().to_string();
+        executor.run(vec!["/bin/kill", "-0", &pid]);
+        executor.run(vec!["/bin/kill", "-9", &pid]);
+    }
+
+    #[test]
+    fn outcome_decodes_output() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let outcome = executor.run(vec![
+            "/bin/bash",
+            "-c",
+            "echo \"  $FOO\"; printf 'a\\xffb' >&2",
+        ]);
+        assert_eq!(outcome.stdout_str(), "  BAR\n");
+        assert_eq!(outcome.stdout_trimmed(), "BAR");
+        assert_eq!(outcome.stderr_str(), "a\u{FFFD}b");
+    }
+
+    #[test]
+    fn outcome_reports_pid() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let output = executor.run(vec!["/bin/bash", "-c", "echo $$"]);
+        assert_eq!(output.pid.unwrap().to_string(), output.stdout_trimmed());
+        assert_eq!(executor.dry_run().run(vec!["/bin/true"]).pid, None);
+    }
+
+    #[test]
+    fn outcome_reports_termination() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let outcome = executor.run_expecting_code(vec!["/bin/bash", "-c", "exit 3"], 3);
+        assert_eq!(outcome.code(), Some(3));
+        assert_eq!(outcome.signal(), None);
+
+        let mut process = executor.spawn(vec!["/bin/sleep", "10"]);
+        process.kill();
+        let outcome = process.wait();
+        assert_eq!(outcome.code(), None);
+        assert_eq!(outcome.signal(), Some(9));
+    }
+
+    #[test]
+    fn try_run_reports_empty_args() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        let args: Vec<&str> = vec![];
+        assert!(matches!(
+            executor.try_run(args),
+            Err(ExecutionError::EmptyArgs)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing command: no arguments were provided")]
+    fn run_with_empty_args() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run(Vec::<&str>::new());
+    }
+
+    #[test]
+    fn try_run_reports_spawn_failure() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        assert!(matches!(
+            executor.try_run(vec!["/this/command/does/not/exist"]),
+            Err(ExecutionError::SpawnFailed { .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to spawn `/this/command/does/not/exist`: No such file")]
+    fn run_reports_missing_program() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        executor.run(vec!["/this/command/does/not/exist"]);
+    }
+
+    #[test]
+    fn try_run_reports_non_zero_exit() {
+        let executor = Executor::new(vec![("FOO", "BAR")]);
+        match executor.try_run(vec!["/bin/bash", "-c", "echo oops >&2; exit 3"]) {
+            Err(ExecutionError::NonZeroExit { status, stderr, .. }) => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stderr, b"oops\n");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
     }
 }
@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+use std::process::{ExitStatus, Output};
+
+/// The result of a completed sub-process.
+///
+/// Mirrors `std::process::Output`, with additional accessors describing
+/// how the sub-process terminated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunOutcome {
+    /// The status (exit code) of the sub-process.
+    pub status: ExitStatus,
+    /// The captured stdout of the sub-process.
+    pub stdout: Vec<u8>,
+    /// The captured stderr of the sub-process.
+    pub stderr: Vec<u8>,
+    /// The process identifier of the sub-process, or `None` if it was
+    /// not launched (such as in a dry run), or the outcome was converted
+    /// from an `Output`.
+    pub pid: Option<u32>,
+    /// The peak resident set size of the sub-process, in bytes.
+    pub(crate) peak_rss: Option<u64>,
+    /// The number of bytes written to stdout/stderr, which may exceed
+    /// the number captured.
+    pub(crate) stdout_len: usize,
+    pub(crate) stderr_len: usize,
+    /// Set if bytes written to stdout/stderr were discarded.
+    pub(crate) stdout_truncated: bool,
+    pub(crate) stderr_truncated: bool,
+    /// The captured stdout/stderr before line endings were normalized, if
+    /// that changed them.
+    pub(crate) raw_stdout: Option<Vec<u8>>,
+    pub(crate) raw_stderr: Option<Vec<u8>>,
+}
+
+impl RunOutcome {
+    /// Returns stdout as a string, replacing invalid UTF-8 with `U+FFFD`.
+    pub fn stdout_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stdout)
+    }
+
+    /// Returns stderr as a string, replacing invalid UTF-8 with `U+FFFD`.
+    pub fn stderr_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stderr)
+    }
+
+    /// Returns stdout as a string, as [`RunOutcome::stdout_str`], without
+    /// leading or trailing whitespace (such as a final newline).
+    pub fn stdout_trimmed(&self) -> String {
+        self.stdout_str().trim().to_string()
+    }
+
+    /// Returns the captured stdout exactly as the sub-process wrote it.
+    ///
+    /// Differs from [`RunOutcome::stdout`] only if line endings were
+    /// normalized by
+    /// [`Executor::with_normalize_newlines`](crate::Executor::with_normalize_newlines).
+    pub fn raw_stdout(&self) -> &[u8] {
+        self.raw_stdout.as_deref().unwrap_or(&self.stdout)
+    }
+
+    /// Returns the captured stderr exactly as the sub-process wrote it, as
+    /// [`RunOutcome::raw_stdout`].
+    pub fn raw_stderr(&self) -> &[u8] {
+        self.raw_stderr.as_deref().unwrap_or(&self.stderr)
+    }
+
+    /// Returns the number of bytes the sub-process wrote to stdout.
+    ///
+    /// Unlike the length of [`RunOutcome::stdout`], this includes any
+    /// bytes discarded beyond
+    /// [`Executor::with_max_output_bytes`](crate::Executor::with_max_output_bytes).
+    /// Only captured output is counted, so this is zero if stdout was
+    /// inherited, discarded or written to a file.
+    pub fn stdout_len(&self) -> usize {
+        self.stdout_len
+    }
+
+    /// Returns the number of bytes the sub-process wrote to stderr, as
+    /// [`RunOutcome::stdout_len`].
+    pub fn stderr_len(&self) -> usize {
+        self.stderr_len
+    }
+
+    /// Returns true if output written to stdout was discarded beyond
+    /// [`Executor::with_max_output_bytes`](crate::Executor::with_max_output_bytes),
+    /// so [`RunOutcome::stdout`] is incomplete.
+    pub fn stdout_truncated(&self) -> bool {
+        self.stdout_truncated
+    }
+
+    /// Returns true if output written to stderr was discarded, as
+    /// [`RunOutcome::stdout_truncated`].
+    pub fn stderr_truncated(&self) -> bool {
+        self.stderr_truncated
+    }
+
+    /// Returns the peak resident set size (the greatest amount of memory
+    /// resident at once) of the sub-process, in bytes.
+    ///
+    /// Only measured on Linux, for commands which run to completion
+    /// through the blocking methods of [`Executor`](crate::Executor);
+    /// `None` for processes which timed out or were launched otherwise.
+    /// The measurement may include memory of the caller, inherited by the
+    /// sub-process before it executed its program, so is best compared
+    /// against that of other commands run by the same caller.
+    pub fn peak_rss_bytes(&self) -> Option<u64> {
+        self.peak_rss
+    }
+
+    /// Returns the exit code of the sub-process, or `None` if it was
+    /// terminated by a signal.
+    pub fn code(&self) -> Option<i32> {
+        self.status.code()
+    }
+
+    /// Returns the signal which terminated the sub-process, or `None`
+    /// if it exited normally.
+    #[cfg(unix)]
+    pub fn signal(&self) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt;
+        self.status.signal()
+    }
+}
+
+impl From<Output> for RunOutcome {
+    fn from(output: Output) -> Self {
+        RunOutcome {
+            status: output.status,
+            stdout_len: output.stdout.len(),
+            stderr_len: output.stderr.len(),
+            stdout_truncated: false,
+            stderr_truncated: false,
+            stdout: output.stdout,
+            stderr: output.stderr,
+            pid: None,
+            peak_rss: None,
+            raw_stdout: None,
+            raw_stderr: None,
+        }
+    }
+}
+
+/// Converts `\r\n` and lone `\r` in `bytes` to `\n`, returning the
+/// original bytes if any were converted.
+pub(crate) fn normalize_newlines(bytes: &mut Vec<u8>) -> Option<Vec<u8>> {
+    if !bytes.contains(&b'\r') {
+        return None;
+    }
+    let mut normalized = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter().peekable();
+    while let Some(&byte) = iter.next() {
+        if byte == b'\r' {
+            iter.next_if_eq(&&b'\n');
+            normalized.push(b'\n');
+        } else {
+            normalized.push(byte);
+        }
+    }
+    Some(std::mem::replace(bytes, normalized))
+}
+
+impl From<RunOutcome> for Output {
+    fn from(outcome: RunOutcome) -> Self {
+        Output {
+            status: outcome.status,
+            stdout: outcome.stdout,
+            stderr: outcome.stderr,
+        }
+    }
+}
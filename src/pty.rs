@@ -0,0 +1,78 @@
+//! Allocation of pseudo-terminals, for testing programs which behave
+//! differently when writing to a terminal.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+/// The size of allocated terminals, in characters.
+const ROWS: u16 = 24;
+const COLUMNS: u16 = 80;
+
+/// Allocates a new pseudo-terminal, returning its controlling side (from
+/// which the caller reads output) and the terminal itself (to which the
+/// child writes).
+pub(crate) fn open() -> io::Result<(File, File)> {
+    let mut controller: RawFd = -1;
+    let mut terminal: RawFd = -1;
+    let mut size = libc::winsize {
+        ws_row: ROWS,
+        ws_col: COLUMNS,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: The out-pointers are valid for writes, and the name and
+    // attributes are optional.
+    let ret = unsafe {
+        libc::openpty(
+            &mut controller,
+            &mut terminal,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::addr_of_mut!(size),
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `openpty` succeeded, so both descriptors are open, and
+    // owned by nothing else.
+    let (controller, terminal) =
+        unsafe { (File::from_raw_fd(controller), File::from_raw_fd(terminal)) };
+    // Neither is inherited by the child, other than as its stdio.
+    for fd in [&controller, &terminal] {
+        set_cloexec(fd)?;
+    }
+    Ok((controller, terminal))
+}
+
+/// Makes the terminal connected to stdout the controlling terminal of
+/// the calling process, within a new session, so that it may be opened
+/// as `/dev/tty`.
+///
+/// This is best-effort: it fails if the process already leads a process
+/// group, as with [`Executor::with_new_process_group`](crate::Executor::with_new_process_group).
+/// Only calls `setsid` and `ioctl`, so is safe to call between `fork`
+/// and `exec`.
+pub(crate) fn acquire() -> io::Result<()> {
+    // SAFETY: Neither call accesses memory of the caller.
+    unsafe {
+        if libc::setsid() < 0 || libc::ioctl(libc::STDOUT_FILENO, libc::TIOCSCTTY as _, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn set_cloexec(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let fd = file.as_raw_fd();
+    // SAFETY: `fd` is open for the lifetime of `file`.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
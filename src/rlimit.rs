@@ -0,0 +1,48 @@
+use std::io;
+
+/// A resource whose consumption by a launched process may be limited,
+/// with [`Executor::with_rlimit`](crate::Executor::with_rlimit).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rlimit {
+    /// The size of the virtual address space, in bytes (`RLIMIT_AS`).
+    AddressSpace,
+    /// The size of core dumps, in bytes (`RLIMIT_CORE`).
+    Core,
+    /// The amount of CPU time, in seconds (`RLIMIT_CPU`).
+    Cpu,
+    /// The size of the data segment, in bytes (`RLIMIT_DATA`).
+    Data,
+    /// The size of files which may be written, in bytes (`RLIMIT_FSIZE`).
+    FileSize,
+    /// The number of open file descriptors (`RLIMIT_NOFILE`).
+    NoFile,
+    /// The size of the stack, in bytes (`RLIMIT_STACK`).
+    Stack,
+}
+
+impl Rlimit {
+    /// Sets the soft and hard limits of the resource for the calling
+    /// process.
+    ///
+    /// Only calls `setrlimit`, so is safe to call between `fork` and `exec`.
+    pub(crate) fn set(self, soft: u64, hard: u64) -> io::Result<()> {
+        let resource = match self {
+            Rlimit::AddressSpace => libc::RLIMIT_AS,
+            Rlimit::Core => libc::RLIMIT_CORE,
+            Rlimit::Cpu => libc::RLIMIT_CPU,
+            Rlimit::Data => libc::RLIMIT_DATA,
+            Rlimit::FileSize => libc::RLIMIT_FSIZE,
+            Rlimit::NoFile => libc::RLIMIT_NOFILE,
+            Rlimit::Stack => libc::RLIMIT_STACK,
+        };
+        let limit = libc::rlimit {
+            rlim_cur: soft as libc::rlim_t,
+            rlim_max: hard as libc::rlim_t,
+        };
+        // SAFETY: `limit` is a valid `rlimit`, which outlives the call.
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
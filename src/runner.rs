@@ -0,0 +1,137 @@
+//! An abstraction over launching commands, so that code which launches
+//! commands can be tested without launching processes.
+
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::process::{ExitStatus, Output};
+use std::sync::Mutex;
+
+use crate::{ExecutionError, Executor, RunOutcome};
+
+/// Launches commands, and awaits their completion.
+///
+/// Implemented by [`Executor`], and by [`MockRunner`] for unit tests of
+/// code which accepts a `&dyn ProcessRunner`.
+pub trait ProcessRunner {
+    /// Launches `args`, returning the captured output, and panicking
+    /// unless it succeeded, as [`Executor::run`].
+    fn run(&self, args: &[&OsStr]) -> RunOutcome;
+
+    /// Launches `args`, returning the captured output on success, as
+    /// [`Executor::try_run`].
+    fn try_run(&self, args: &[&OsStr]) -> Result<RunOutcome, ExecutionError>;
+}
+
+impl<K, V> ProcessRunner for Executor<K, V>
+where
+    K: AsRef<OsStr> + Clone,
+    V: AsRef<OsStr> + Clone,
+{
+    #[track_caller]
+    fn run(&self, args: &[&OsStr]) -> RunOutcome {
+        Executor::run(self, args)
+    }
+
+    fn try_run(&self, args: &[&OsStr]) -> Result<RunOutcome, ExecutionError> {
+        Executor::try_run(self, args)
+    }
+}
+
+/// A [`ProcessRunner`] which launches nothing, recording the commands it
+/// is asked to run, and answering each with the next of a sequence of
+/// pre-programmed outputs.
+///
+/// Outputs which did not exit successfully are treated as failures of
+/// the command, so `run` panics, and `try_run` returns
+/// [`ExecutionError::NonZeroExit`].
+#[derive(Debug, Default)]
+pub struct MockRunner {
+    outputs: Mutex<VecDeque<RunOutcome>>,
+    invocations: Mutex<Vec<Vec<OsString>>>,
+}
+
+impl MockRunner {
+    /// Initializes a runner with no outputs programmed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers the next command with `output`, after any outputs
+    /// programmed previously.
+    pub fn with_output<O: Into<RunOutcome>>(self, output: O) -> Self {
+        self.outputs.lock().unwrap().push_back(output.into());
+        self
+    }
+
+    /// Answers the next command with a successful exit, and `stdout`.
+    pub fn with_stdout<B: Into<Vec<u8>>>(self, stdout: B) -> Self {
+        self.with_output(Output {
+            status: exit_status(0),
+            stdout: stdout.into(),
+            stderr: Vec::new(),
+        })
+    }
+
+    /// Answers the next command with the exit code `code`, and `stderr`.
+    pub fn with_exit_code<B: Into<Vec<u8>>>(self, code: i32, stderr: B) -> Self {
+        self.with_output(Output {
+            status: exit_status(code),
+            stdout: Vec::new(),
+            stderr: stderr.into(),
+        })
+    }
+
+    /// Returns the commands which the runner has been asked to run, in
+    /// order.
+    pub fn invocations(&self) -> Vec<Vec<OsString>> {
+        self.invocations.lock().unwrap().clone()
+    }
+}
+
+impl ProcessRunner for MockRunner {
+    #[track_caller]
+    fn run(&self, args: &[&OsStr]) -> RunOutcome {
+        match self.try_run(args) {
+            Ok(outcome) => outcome,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    #[track_caller]
+    fn try_run(&self, args: &[&OsStr]) -> Result<RunOutcome, ExecutionError> {
+        if args.is_empty() {
+            return Err(ExecutionError::EmptyArgs);
+        }
+        let command: Vec<OsString> = args.iter().map(|arg| arg.to_os_string()).collect();
+        self.invocations.lock().unwrap().push(command.clone());
+        let next = self.outputs.lock().unwrap().pop_front();
+        let outcome = match next {
+            Some(outcome) => outcome,
+            None => panic!(
+                "MockRunner has no output programmed for `{}`",
+                crate::command_line(&command)
+            ),
+        };
+        if outcome.status.success() {
+            return Ok(outcome);
+        }
+        Err(ExecutionError::NonZeroExit {
+            command,
+            status: outcome.status,
+            stdout: outcome.stdout,
+            stderr: outcome.stderr,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+use std::io;
+use std::process::{Child, ExitStatus, Output};
+use std::time::Duration;
+
+use crate::outcome::normalize_newlines;
+use crate::spawn::{self, Drain, Spawned};
+use crate::{panic_in_context, Execution, RunOutcome};
+
+/// A handle to a process launched in the background by
+/// [`Executor::spawn`](crate::Executor::spawn).
+///
+/// The process is killed when the handle is dropped, so a test which
+/// panics midway does not leak it, unless it is explicitly released
+/// with [`RunningProcess::leak`].
+pub struct RunningProcess {
+    exec: Execution,
+    child: Child,
+    stdout: Option<Drain>,
+    stderr: Option<Drain>,
+    status: Option<ExitStatus>,
+    /// The context label of the executor.
+    context: Option<String>,
+    /// Whether the process is killed if the handle is dropped, before
+    /// the process is reaped.
+    kill_on_drop: bool,
+}
+
+impl RunningProcess {
+    pub(crate) fn new(exec: Execution, spawned: Spawned, context: Option<String>) -> Self {
+        RunningProcess {
+            exec,
+            child: spawned.child,
+            stdout: spawned.stdout,
+            stderr: spawned.stderr,
+            status: None,
+            context,
+            kill_on_drop: true,
+        }
+    }
+
+    /// Returns the OS-assigned process identifier of the process.
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Returns an iterator over the lines of stdout, without their line
+    /// endings, which yields each line as soon as the process writes it.
+    ///
+    /// Iteration blocks until the next line is complete, and ends once
+    /// the process closes stdout (typically by exiting). Lines which
+    /// are not valid UTF-8 are yielded as errors. Lines remain part of
+    /// the output returned by [`RunningProcess::wait`].
+    ///
+    /// Yields nothing if stdout is not captured, or if output beyond
+    /// [`Executor::with_max_output_bytes`](crate::Executor::with_max_output_bytes)
+    /// was discarded.
+    pub fn stdout_lines(&self) -> impl Iterator<Item = io::Result<String>> + '_ {
+        self.stdout.iter().flat_map(Drain::lines)
+    }
+
+    /// Kills the process, and awaits its termination.
+    ///
+    /// Does nothing if the process has already been reaped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the process cannot be killed.
+    #[track_caller]
+    pub fn kill(&mut self) {
+        if self.status.is_none() {
+            let status = match spawn::kill(&mut self.child) {
+                Ok(status) => status,
+                Err(err) => self.panic("Failed to kill process", err),
+            };
+            self.status = Some(status);
+        }
+    }
+
+    /// Awaits the termination of the process for up to `timeout`,
+    /// returning its exit status if it terminated.
+    ///
+    /// If the process is still running after `timeout`, it is left
+    /// running, and `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the process cannot be awaited.
+    #[track_caller]
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<ExitStatus> {
+        if self.status.is_none() {
+            self.status = match spawn::wait_timeout(&mut self.child, timeout) {
+                Ok(status) => status,
+                Err(err) => self.panic("Failed to await process", err),
+            };
+        }
+        self.status
+    }
+
+    /// Waits for `delay`, and then checks that the process is still
+    /// running, such as to confirm that a server did not crash on startup.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the process has terminated, describing how, with its
+    /// captured output, or if the process cannot be checked.
+    #[track_caller]
+    pub fn assert_alive_after(&mut self, delay: Duration) {
+        std::thread::sleep(delay);
+        if self.status.is_none() {
+            self.status = match self.child.try_wait() {
+                Ok(status) => status,
+                Err(err) => self.panic("Failed to check process", err),
+            };
+        }
+        if self.status.is_some() {
+            self.fail(
+                format_args!("Expected process to be running after {:?}", delay),
+                delay,
+            );
+        }
+    }
+
+    /// Awaits the termination of the process, returning its captured output.
+    ///
+    /// Unlike [`Executor::run`](crate::Executor::run), the exit status is
+    /// not checked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the process cannot be awaited.
+    #[track_caller]
+    pub fn wait(mut self) -> RunOutcome {
+        let status = match self.status {
+            Some(status) => status,
+            None => match self.child.wait() {
+                Ok(status) => status,
+                Err(err) => self.panic("Failed to await process", err),
+            },
+        };
+        self.status = Some(status);
+        let mut stdout = self.stdout.take().map(Drain::finish).unwrap_or_default();
+        let mut stderr = self.stderr.take().map(Drain::finish).unwrap_or_default();
+        let (raw_stdout, raw_stderr) = if self.exec.normalize_newlines {
+            (
+                normalize_newlines(&mut stdout.bytes),
+                normalize_newlines(&mut stderr.bytes),
+            )
+        } else {
+            (None, None)
+        };
+        RunOutcome {
+            status,
+            pid: Some(self.pid()),
+            peak_rss: None,
+            stdout: stdout.bytes,
+            stderr: stderr.bytes,
+            stdout_len: stdout.len,
+            stderr_len: stderr.len,
+            stdout_truncated: stdout.truncated,
+            stderr_truncated: stderr.truncated,
+            raw_stdout,
+            raw_stderr,
+        }
+    }
+
+    /// Releases the process, allowing it to outlive the handle, and
+    /// returns its process identifier.
+    ///
+    /// By default, processes are killed when their handle is dropped,
+    /// as a process which outlives a test is usually a bug. The process
+    /// is not awaited, so on Unix it remains a zombie until the caller
+    /// exits, and its output is discarded.
+    pub fn leak(mut self) -> u32 {
+        self.kill_on_drop = false;
+        self.pid()
+    }
+
+    /// Kills the process, if it is still running, and panics with `msg`,
+    /// followed by the process and its captured output.
+    ///
+    /// If the process was killed, it is reported as having exceeded
+    /// `timeout`, rather than by how it terminated.
+    #[track_caller]
+    pub(crate) fn fail(&mut self, msg: fmt::Arguments, timeout: Duration) -> ! {
+        if self.status.is_none() {
+            self.kill();
+            self.exec.timed_out = Some(timeout);
+        }
+        let status = self.status.unwrap();
+        let mut stdout = self
+            .stdout
+            .take()
+            .map(Drain::finish_within)
+            .unwrap_or_default();
+        let mut stderr = self
+            .stderr
+            .take()
+            .map(Drain::finish_within)
+            .unwrap_or_default();
+        if self.exec.normalize_newlines {
+            self.exec.raw_stdout = normalize_newlines(&mut stdout.bytes);
+            self.exec.raw_stderr = normalize_newlines(&mut stderr.bytes);
+        }
+        self.exec.stdout_len = stdout.len;
+        self.exec.stderr_len = stderr.len;
+        self.exec.stdout_truncated = stdout.truncated;
+        self.exec.stderr_truncated = stderr.truncated;
+        self.exec.result = Some(Output {
+            status,
+            stdout: stdout.bytes,
+            stderr: stderr.bytes,
+        });
+        panic_in_context(
+            self.context.as_deref(),
+            format_args!("{}\n{}", msg, self.exec),
+        )
+    }
+
+    /// Panics, describing the failed operation and the process.
+    #[track_caller]
+    fn panic(&self, msg: &str, err: io::Error) -> ! {
+        panic_in_context(
+            self.context.as_deref(),
+            format_args!("{}: {}\n{}", msg, err, self.exec),
+        )
+    }
+}
+
+impl Drop for RunningProcess {
+    fn drop(&mut self) {
+        if self.kill_on_drop && self.status.is_none() {
+            let _ = spawn::kill(&mut self.child);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Temporary script files, for running scripts too long to pass inline.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes the scripts written by a single process.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A script written to the temporary directory, which is removed when
+/// dropped (including while unwinding from a panic).
+pub(crate) struct TempScript {
+    path: PathBuf,
+}
+
+impl TempScript {
+    /// Writes `contents` to a new file, which is readable and executable
+    /// only by the current user on Unix.
+    ///
+    /// The file is created exclusively, so an existing file (or symlink)
+    /// at the chosen path is never written through.
+    pub(crate) fn new(contents: &str) -> io::Result<Self> {
+        loop {
+            let path = std::env::temp_dir().join(format!(
+                "test_process_executor-{}-{}.script",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut file = match create(&path) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                result => result?,
+            };
+            let script = TempScript { path };
+            file.write_all(contents.as_bytes())?;
+            return Ok(script);
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempScript {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+fn create(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o700)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
@@ -0,0 +1,228 @@
+//! Shell-like parsing and quoting of command lines.
+
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fmt;
+
+/// A command line which could not be split into arguments.
+#[derive(Debug, PartialEq)]
+pub(crate) enum SplitError {
+    /// A quote was opened, but never closed.
+    UnbalancedQuote(char),
+    /// The command line ended with an unescaped backslash.
+    TrailingBackslash,
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SplitError::UnbalancedQuote(quote) => write!(f, "Unbalanced quote ({})", quote),
+            SplitError::TrailingBackslash => write!(f, "Trailing backslash"),
+        }
+    }
+}
+
+/// Splits a command line into arguments, following the quoting rules
+/// of a POSIX shell:
+///
+/// - Unquoted whitespace separates arguments.
+/// - Single quotes preserve everything until the closing quote.
+/// - Double quotes preserve everything until the closing quote, other
+///   than backslash-escaped `"`, `\`, `$` and `` ` ``.
+/// - Outside of quotes, a backslash escapes the following character.
+///
+/// No other shell features (variables, globs, etc) are supported.
+pub(crate) fn split(line: &str) -> Result<Vec<String>, SplitError> {
+    let mut args = Vec::new();
+    // The argument being built, if any. Tracked separately from its
+    // contents so that empty quotes (`''`) produce an empty argument.
+    let mut arg: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(arg) = arg.take() {
+                    args.push(arg);
+                }
+            }
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(SplitError::UnbalancedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') | Some(c @ '$') | Some(c @ '`') => {
+                                arg.push(c)
+                            }
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err(SplitError::UnbalancedQuote('"')),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(SplitError::UnbalancedQuote('"')),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+                None => return Err(SplitError::TrailingBackslash),
+            },
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+/// Expands references to variables in `arg`, as `$NAME` or `${NAME}`,
+/// with their value according to `lookup`.
+///
+/// `$$` expands to a single `$`. Any other `$` which does not begin a
+/// reference (such as in `$1` or at the end of `arg`) is preserved.
+pub(crate) fn interpolate<F, E>(arg: &str, mut lookup: F) -> Result<OsString, E>
+where
+    F: FnMut(&str) -> Result<OsString, E>,
+{
+    let mut expanded = OsString::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find('$') {
+        expanded.push(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(tail) = after.strip_prefix('$') {
+            expanded.push("$");
+            rest = tail;
+            continue;
+        }
+        let (name, tail) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", after),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+        if valid && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            expanded.push(lookup(name)?);
+            rest = tail;
+        } else {
+            expanded.push("$");
+            rest = after;
+        }
+    }
+    expanded.push(rest);
+    Ok(expanded)
+}
+
+/// The arguments which run `script` with the platform's shell.
+pub(crate) fn command(script: &str) -> [&str; 3] {
+    if cfg!(windows) {
+        ["cmd", "/C", script]
+    } else {
+        ["/bin/sh", "-c", script]
+    }
+}
+
+/// Quotes an argument, if necessary, so that a POSIX shell would
+/// interpret it as a single word with the same contents.
+///
+/// Arguments consisting only of characters with no special meaning
+/// are returned unmodified; all others are wrapped in single quotes.
+pub(crate) fn quote(arg: &str) -> Cow<'_, str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%^".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return Cow::Borrowed(arg);
+    }
+    // Single quotes cannot be escaped within single quotes, so the
+    // quoted string is closed, followed by an escaped quote, and reopened.
+    Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_on_whitespace() {
+        assert_eq!(
+            split("  ls  -la\t/tmp ").unwrap(),
+            vec!["ls", "-la", "/tmp"]
+        );
+        assert!(split("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn split_quoted_arguments() {
+        assert_eq!(
+            split(r#"bash -c 'echo "hi there"' "a \"b\" \c" '' x"y"z"#).unwrap(),
+            vec!["bash", "-c", "echo \"hi there\"", "a \"b\" \\c", "", "xyz"]
+        );
+        assert_eq!(split(r"a\ b \'c").unwrap(), vec!["a b", "'c"]);
+    }
+
+    #[test]
+    fn quote_arguments() {
+        assert_eq!(quote("/bin/ls"), "/bin/ls");
+        assert_eq!(quote("--flag=a,b"), "--flag=a,b");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("hello world"), "'hello world'");
+        assert_eq!(quote("$FOO"), "'$FOO'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn quote_round_trips_through_split() {
+        let args = [
+            "echo",
+            "a b",
+            "it's",
+            "\"quoted\"",
+            "",
+            "$HOME",
+            "back\\slash",
+        ];
+        let line: Vec<_> = args.iter().map(|arg| quote(arg)).collect();
+        assert_eq!(split(&line.join(" ")).unwrap(), args);
+    }
+
+    #[test]
+    fn interpolate_variables() {
+        let lookup = |name: &str| match name {
+            "FOO" => Ok(OsString::from("bar")),
+            _ => Err(name.to_string()),
+        };
+        assert_eq!(interpolate("$FOO/bin", lookup).unwrap(), "bar/bin");
+        assert_eq!(interpolate("${FOO}x-$FOO", lookup).unwrap(), "barx-bar");
+        assert_eq!(
+            interpolate("$$FOO $1 ${ $", lookup).unwrap(),
+            "$FOO $1 ${ $"
+        );
+        assert_eq!(interpolate("a$BAZ", lookup), Err("BAZ".to_string()));
+    }
+
+    #[test]
+    fn split_unbalanced() {
+        assert_eq!(split("echo 'hi"), Err(SplitError::UnbalancedQuote('\'')));
+        assert_eq!(
+            split("echo \"hi\\\""),
+            Err(SplitError::UnbalancedQuote('"'))
+        );
+        assert_eq!(split("echo \\"), Err(SplitError::TrailingBackslash));
+    }
+}
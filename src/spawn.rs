@@ -0,0 +1,833 @@
+//! Spawning and awaiting sub-processes, while feeding stdin and
+//! capturing stdout/stderr.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStderr, ChildStdout, Command, ExitStatus, Output, Stdio};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{StdioMode, Stream};
+
+/// A writer shared between executions, to which output is copied.
+pub(crate) type Sink = Mutex<dyn Write + Send>;
+
+/// The lines written to stdout and stderr, in the order they were read.
+pub(crate) type TaggedLines = Mutex<Vec<(Stream, String)>>;
+
+/// How frequently a running child is polled for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long to wait for a killed child's pipes to finish draining.
+const DRAIN_GRACE: Duration = Duration::from_millis(100);
+
+/// The bytes read from a pipe.
+#[derive(Default)]
+pub(crate) struct Drained {
+    /// The bytes retained, up to any limit.
+    pub bytes: Vec<u8>,
+    /// The total number of bytes read, including any not retained.
+    pub len: usize,
+    /// Set if any bytes read were not retained.
+    pub truncated: bool,
+}
+
+impl Drained {
+    /// Records `chunk`, retaining only up to `limit` bytes in total.
+    pub(crate) fn extend(&mut self, chunk: &[u8], limit: Option<usize>) {
+        let room = limit.map_or(chunk.len(), |limit| limit.saturating_sub(self.bytes.len()));
+        self.bytes
+            .extend_from_slice(&chunk[..room.min(chunk.len())]);
+        self.len += chunk.len();
+        self.truncated |= room < chunk.len();
+    }
+}
+
+/// Reads a pipe to completion on a background thread.
+///
+/// Bytes are made available as they are read, so the contents may be
+/// inspected even if the pipe is never closed. If a `tee` is provided,
+/// bytes are also copied to it as they are read. The pipe is always read
+/// to completion, but bytes beyond the `limit` (if any) are discarded.
+pub(crate) struct Drain {
+    shared: Arc<(Mutex<DrainState>, Condvar)>,
+}
+
+/// The state shared with a drain's background thread, which notifies
+/// the condition variable whenever it changes.
+#[derive(Default)]
+struct DrainState {
+    drained: Drained,
+    /// Set once the pipe has been read to completion.
+    closed: bool,
+}
+
+impl Drain {
+    fn new<R: Read + Send + 'static>(
+        mut pipe: R,
+        mut tee: Option<Box<dyn Write + Send>>,
+        limit: Option<usize>,
+    ) -> Self {
+        let shared = Arc::new((Mutex::new(DrainState::default()), Condvar::new()));
+        let writer = shared.clone();
+        thread::spawn(move || {
+            let (state, changed) = &*writer;
+            let mut chunk = [0; 4096];
+            loop {
+                let n = match pipe.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                state.lock().unwrap().drained.extend(&chunk[..n], limit);
+                changed.notify_all();
+                if let Some(tee) = tee.as_mut() {
+                    // Echoing is best-effort; the output is still captured.
+                    let _ = tee.write_all(&chunk[..n]).and_then(|_| tee.flush());
+                }
+            }
+            // Flushes any partial line to the tee before the pipe is
+            // reported as closed.
+            drop(tee);
+            state.lock().unwrap().closed = true;
+            changed.notify_all();
+        });
+        Drain { shared }
+    }
+
+    /// Waits for the pipe to close, returning everything read.
+    pub(crate) fn finish(self) -> Drained {
+        let (state, changed) = &*self.shared;
+        let state = changed
+            .wait_while(state.lock().unwrap(), |state| !state.closed)
+            .unwrap();
+        Self::take(state)
+    }
+
+    /// Waits up to [`DRAIN_GRACE`] for the pipe to close, returning
+    /// whatever has been read by then.
+    pub(crate) fn finish_within(self) -> Drained {
+        let (state, changed) = &*self.shared;
+        let (state, _) = changed
+            .wait_timeout_while(state.lock().unwrap(), DRAIN_GRACE, |state| !state.closed)
+            .unwrap();
+        Self::take(state)
+    }
+
+    /// Waits until `deadline` for the pipe to close, returning whatever
+    /// has been read by then, and whether the pipe closed.
+    fn finish_before(self, deadline: Instant) -> (Drained, bool) {
+        let (state, changed) = &*self.shared;
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let (state, _) = changed
+            .wait_timeout_while(state.lock().unwrap(), timeout, |state| !state.closed)
+            .unwrap();
+        let closed = state.closed;
+        (Self::take(state), closed)
+    }
+
+    fn take(mut state: MutexGuard<DrainState>) -> Drained {
+        std::mem::take(&mut state.drained)
+    }
+
+    /// Returns a function which blocks until the pipe has been closed.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn closed(&self) -> impl FnOnce() + Send + 'static {
+        let shared = self.shared.clone();
+        move || {
+            let (state, changed) = &*shared;
+            drop(changed.wait_while(state.lock().unwrap(), |state| !state.closed));
+        }
+    }
+
+    /// Returns an iterator over the lines read from the pipe, blocking
+    /// until each line is complete (or the pipe is closed).
+    pub(crate) fn lines(&self) -> Lines<'_> {
+        Lines {
+            drain: self,
+            pos: 0,
+        }
+    }
+}
+
+/// An iterator over the lines read by a [`Drain`], without their line
+/// endings.
+pub(crate) struct Lines<'a> {
+    drain: &'a Drain,
+    /// The offset of the next line within the drained bytes.
+    pos: usize,
+}
+
+impl Iterator for Lines<'_> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (state, changed) = &*self.drain.shared;
+        let mut state = state.lock().unwrap();
+        loop {
+            let rest = &state.drained.bytes[self.pos..];
+            let line = match rest.iter().position(|&b| b == b'\n') {
+                Some(end) => {
+                    self.pos += end + 1;
+                    &rest[..end]
+                }
+                None if state.closed && rest.is_empty() => return None,
+                None if state.closed => {
+                    self.pos += rest.len();
+                    rest
+                }
+                None => {
+                    state = changed.wait(state).unwrap();
+                    continue;
+                }
+            };
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            return Some(
+                String::from_utf8(line.to_vec())
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            );
+        }
+    }
+}
+
+/// Configuration for launching a child.
+pub(crate) struct Config<'a> {
+    /// Bytes written to the child's stdin. If unset, stdin is closed.
+    pub stdin: Option<&'a [u8]>,
+    /// A file from which the child reads stdin, in place of `stdin`.
+    pub stdin_file: Option<&'a Path>,
+    /// The maximum duration the child may run before being killed.
+    pub timeout: Option<Duration>,
+    /// The signal sent to a child which exceeds the timeout, and how long
+    /// it is given to exit before being killed. If unset, it is killed
+    /// immediately.
+    #[cfg(unix)]
+    pub timeout_kill: Option<(i32, Duration)>,
+    /// Where stdout is written.
+    pub stdout: &'a StdioMode,
+    /// Where stderr is written.
+    pub stderr: &'a StdioMode,
+    /// If set, output written to a file is appended to it, rather than
+    /// replacing its contents.
+    pub stdout_append: bool,
+    pub stderr_append: bool,
+    /// If set, captured output is also echoed to the caller's
+    /// stdout/stderr as it is read.
+    pub streaming: bool,
+    /// The maximum number of bytes of each stream which are retained.
+    pub max_output_bytes: Option<usize>,
+    /// If set, the child inherits stdin/stdout/stderr from the caller,
+    /// overriding all other stdio configuration.
+    pub inherit_stdio: bool,
+    /// If set, stderr is written to the same destination as stdout.
+    pub merge_output: bool,
+    /// If set, stdout and stderr are written to a new pseudo-terminal,
+    /// from which stdout is captured.
+    #[cfg(all(unix, feature = "pty"))]
+    pub pty: bool,
+    /// If set, captured stdout is copied to this writer as it is read,
+    /// in place of echoing it to the caller's stdout.
+    pub stdout_sink: Option<&'a Arc<Sink>>,
+    /// If set, the lines of captured stdout and stderr are recorded here
+    /// as they are read, in place of any other copying.
+    pub tagged: Option<&'a Arc<TaggedLines>>,
+}
+
+impl Config<'_> {
+    /// The `Stdio` from which the child reads stdin.
+    pub(crate) fn stdin(&self) -> io::Result<Stdio> {
+        if self.inherit_stdio {
+            Ok(Stdio::inherit())
+        } else if let Some(path) = self.stdin_file {
+            let file = File::open(path).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("Failed to open {}: {}", path.display(), err),
+                )
+            })?;
+            Ok(Stdio::from(file))
+        } else if self.stdin.is_some() {
+            Ok(Stdio::piped())
+        } else {
+            Ok(Stdio::null())
+        }
+    }
+
+    /// The `Stdio` to which the child writes stdout.
+    pub(crate) fn stdout(&self) -> io::Result<Stdio> {
+        self.output(self.stdout, self.stdout_append)
+    }
+
+    /// The `Stdio` to which the child writes stderr.
+    pub(crate) fn stderr(&self) -> io::Result<Stdio> {
+        self.output(self.stderr, self.stderr_append)
+    }
+
+    /// Configures the stdout and stderr of `command`.
+    ///
+    /// If output is merged into a single pipe (or terminal), returns its
+    /// reading end, which is not otherwise accessible from the child. The
+    /// command holds the writing end open until it is reconfigured or
+    /// dropped.
+    pub(crate) fn connect_output(
+        &self,
+        command: &mut Command,
+    ) -> io::Result<Option<Box<dyn Read + Send>>> {
+        #[cfg(all(unix, feature = "pty"))]
+        if self.pty && !self.inherit_stdio {
+            use std::os::unix::process::CommandExt;
+            let (controller, terminal) = crate::pty::open()?;
+            command.stdout(terminal.try_clone()?).stderr(terminal);
+            // SAFETY: `pty::acquire` only calls `setsid` and `ioctl`, which
+            // are async-signal-safe. Its failure is not fatal, as output is
+            // still written to the terminal.
+            unsafe {
+                command.pre_exec(|| {
+                    let _ = crate::pty::acquire();
+                    Ok(())
+                });
+            }
+            return Ok(Some(Box::new(controller)));
+        }
+        if !self.merge_output || self.inherit_stdio {
+            command.stdout(self.stdout()?).stderr(self.stderr()?);
+            return Ok(None);
+        }
+        match self.stdout {
+            StdioMode::Capture => {
+                let (reader, writer) = io::pipe()?;
+                command.stdout(writer.try_clone()?).stderr(writer);
+                return Ok(Some(Box::new(reader)));
+            }
+            StdioMode::Inherit => command.stdout(Stdio::inherit()).stderr(io::stdout()),
+            StdioMode::Null => command.stdout(Stdio::null()).stderr(Stdio::null()),
+            StdioMode::File(path) => {
+                let file = create(path, self.stdout_append)?;
+                command.stdout(file.try_clone()?).stderr(file)
+            }
+        };
+        Ok(None)
+    }
+
+    /// The writer to which captured stdout is copied as it is read, if any.
+    pub(crate) fn stdout_tee(&self) -> Option<Box<dyn Write + Send>> {
+        if let Some(lines) = self.tagged {
+            return Some(Box::new(Tagger::new(Stream::Stdout, lines)));
+        }
+        match self.stdout_sink {
+            Some(sink) => Some(Box::new(Shared(sink.clone()))),
+            None => self.streamed(io::stdout()),
+        }
+    }
+
+    /// The writer to which captured stderr is copied as it is read, if any.
+    pub(crate) fn stderr_tee(&self) -> Option<Box<dyn Write + Send>> {
+        if let Some(lines) = self.tagged {
+            return Some(Box::new(Tagger::new(Stream::Stderr, lines)));
+        }
+        self.streamed(io::stderr())
+    }
+
+    /// Returns `writer`, if output is echoed to the caller.
+    fn streamed<W: Write + Send + 'static>(&self, writer: W) -> Option<Box<dyn Write + Send>> {
+        self.streaming
+            .then(|| Box::new(writer) as Box<dyn Write + Send>)
+    }
+
+    fn output(&self, mode: &StdioMode, append: bool) -> io::Result<Stdio> {
+        if self.inherit_stdio {
+            return Ok(Stdio::inherit());
+        }
+        output_stdio(mode, append)
+    }
+}
+
+/// Returns a `Stdio` writing output as directed by `mode`.
+fn output_stdio(mode: &StdioMode, append: bool) -> io::Result<Stdio> {
+    match mode {
+        StdioMode::Capture => Ok(Stdio::piped()),
+        StdioMode::Inherit => Ok(Stdio::inherit()),
+        StdioMode::Null => Ok(Stdio::null()),
+        StdioMode::File(path) => create(path, append).map(Stdio::from),
+    }
+}
+
+/// Creates the file at `path` for writing output, either truncating it
+/// or appending to it.
+fn create(path: &Path, append: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).truncate(true);
+    }
+    options.create(true).open(path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("Failed to create {}: {}", path.display(), err),
+        )
+    })
+}
+
+/// The output of a child, along with its process identifier and the
+/// number of bytes it wrote to each captured stream (which may exceed the
+/// number retained).
+pub(crate) struct Captured {
+    pub pid: Option<u32>,
+    pub output: Output,
+    pub stdout_len: usize,
+    pub stderr_len: usize,
+    /// Set if bytes written to stdout/stderr were not retained.
+    pub stdout_truncated: bool,
+    pub stderr_truncated: bool,
+    /// The peak resident set size of the child, in bytes, if measured.
+    pub peak_rss: Option<u64>,
+}
+
+impl Captured {
+    pub(crate) fn new(
+        pid: Option<u32>,
+        status: ExitStatus,
+        stdout: Drained,
+        stderr: Drained,
+    ) -> Self {
+        Captured {
+            pid,
+            output: Output {
+                status,
+                stdout: stdout.bytes,
+                stderr: stderr.bytes,
+            },
+            stdout_len: stdout.len,
+            stderr_len: stderr.len,
+            stdout_truncated: stdout.truncated,
+            stderr_truncated: stderr.truncated,
+            peak_rss: None,
+        }
+    }
+}
+
+/// The result of waiting on a child.
+pub(crate) enum Waited {
+    /// The child exited of its own accord.
+    Completed(Captured),
+    /// The child exceeded the deadline, and was killed. Contains any
+    /// output captured before the child was killed.
+    TimedOut(Captured),
+}
+
+/// A launched child, along with the threads servicing its pipes.
+///
+/// Streams which are not captured have no drain.
+pub(crate) struct Spawned {
+    pub child: Child,
+    pub stdout: Option<Drain>,
+    pub stderr: Option<Drain>,
+}
+
+/// Launches `command`, capturing stdout/stderr.
+///
+/// Like `Command::output`, pipes are serviced concurrently, so a child
+/// blocked on writing output will not prevent stdin from being written
+/// (or vice versa).
+pub(crate) fn spawn(command: &mut Command, config: &Config) -> io::Result<Spawned> {
+    command.stdin(config.stdin()?);
+    let merged = config.connect_output(command)?;
+    let mut child = command.spawn()?;
+    if merged.is_some() {
+        // Releases the command's copies of the merged pipe, so that EOF
+        // is observed once the child exits.
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+    }
+    feed_stdin(&mut child, config);
+    let stdout = match merged {
+        Some(pipe) => Some(drain_stdout(pipe, config)),
+        None => child.stdout.take().map(|pipe| drain_stdout(pipe, config)),
+    };
+    let stderr = child.stderr.take().map(|pipe| drain_stderr(pipe, config));
+    Ok(Spawned {
+        child,
+        stdout,
+        stderr,
+    })
+}
+
+/// Writes the configured stdin (if any) to the child on a background thread.
+fn feed_stdin(child: &mut Child, config: &Config) {
+    if let (Some(mut pipe), Some(data)) = (child.stdin.take(), config.stdin) {
+        let data = data.to_vec();
+        // The child is free to exit without consuming all of stdin, so
+        // errors (typically a broken pipe) are ignored. Dropping the
+        // pipe closes it, signalling EOF.
+        thread::spawn(move || {
+            let _ = pipe.write_all(&data);
+        });
+    }
+}
+
+pub(crate) fn drain_stdout<R: Read + Send + 'static>(pipe: R, config: &Config) -> Drain {
+    Drain::new(pipe, config.stdout_tee(), config.max_output_bytes)
+}
+
+fn drain_stderr(pipe: ChildStderr, config: &Config) -> Drain {
+    Drain::new(pipe, config.stderr_tee(), config.max_output_bytes)
+}
+
+/// Writes to a [`Sink`], locking it for each write.
+struct Shared(Arc<Sink>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(&self.0).write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        lock(&self.0).write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock(&self.0).flush()
+    }
+}
+
+/// Records each complete line written to it in [`TaggedLines`], along
+/// with the stream from which it was read.
+///
+/// Any final line without a line ending is recorded when dropped.
+struct Tagger {
+    stream: Stream,
+    lines: Arc<TaggedLines>,
+    partial: Vec<u8>,
+}
+
+impl Tagger {
+    fn new(stream: Stream, lines: &Arc<TaggedLines>) -> Self {
+        Tagger {
+            stream,
+            lines: lines.clone(),
+            partial: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, mut line: &[u8]) {
+        line = line.strip_suffix(b"\n").unwrap_or(line);
+        line = line.strip_suffix(b"\r").unwrap_or(line);
+        let line = String::from_utf8_lossy(line).into_owned();
+        let mut lines = self.lines.lock().unwrap_or_else(|err| err.into_inner());
+        lines.push((self.stream, line));
+    }
+}
+
+impl Write for Tagger {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            self.record(&line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Tagger {
+    fn drop(&mut self) {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.record(&line);
+        }
+    }
+}
+
+/// Locks `sink`, even if a previous writer panicked.
+fn lock(sink: &Sink) -> MutexGuard<'_, dyn Write + Send + 'static> {
+    sink.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Launches `commands` as a pipeline, with the stdout of each connected
+/// to the stdin of the next, and waits for all of them to complete.
+///
+/// Returns the result of each command. Only the stdout of the last
+/// command is captured; stderr is captured for every command. The
+/// commands are consumed, as each holds its end of a pipe open until
+/// dropped. Errors are returned alongside the index of the command
+/// which caused them.
+pub(crate) fn pipeline(
+    commands: Vec<Command>,
+    config: &Config,
+) -> Result<Vec<Captured>, (usize, io::Error)> {
+    let count = commands.len();
+    let mut spawned: Vec<Spawned> = Vec::with_capacity(count);
+    let mut prev_stdout: Option<ChildStdout> = None;
+    for (index, mut command) in commands.into_iter().enumerate() {
+        let last = index + 1 == count;
+        let stdin = match prev_stdout.take() {
+            Some(pipe) => Ok(Stdio::from(pipe)),
+            None => config.stdin(),
+        };
+        let stdout = if last {
+            config.stdout()
+        } else {
+            Ok(Stdio::piped())
+        };
+        let child = stdout.and_then(|stdout| {
+            command
+                .stdin(stdin?)
+                .stdout(stdout)
+                .stderr(config.stderr()?)
+                .spawn()
+        });
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => {
+                for mut earlier in spawned {
+                    let _ = kill(&mut earlier.child);
+                }
+                return Err((index, err));
+            }
+        };
+        feed_stdin(&mut child, config);
+        let stdout = if last {
+            child.stdout.take().map(|pipe| drain_stdout(pipe, config))
+        } else {
+            prev_stdout = child.stdout.take();
+            None
+        };
+        let stderr = child.stderr.take().map(|pipe| drain_stderr(pipe, config));
+        spawned.push(Spawned {
+            child,
+            stdout,
+            stderr,
+        });
+    }
+    spawned
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut spawned)| {
+            Ok(Captured::new(
+                Some(spawned.child.id()),
+                spawned.child.wait().map_err(|err| (index, err))?,
+                spawned.stdout.map(Drain::finish).unwrap_or_default(),
+                spawned.stderr.map(Drain::finish).unwrap_or_default(),
+            ))
+        })
+        .collect()
+}
+
+/// Launches `command`, capturing stdout/stderr, and waits for it to
+/// complete. If the deadline is exceeded the child is killed and reaped.
+pub(crate) fn output(command: &mut Command, config: &Config) -> io::Result<Waited> {
+    let Spawned {
+        mut child,
+        stdout,
+        stderr,
+    } = spawn(command, config)?;
+    let pid = Some(child.id());
+    let deadline = config.timeout.map(|timeout| Instant::now() + timeout);
+
+    let completed = match config.timeout {
+        Some(timeout) => wait_timeout_measuring(&mut child, timeout)?,
+        None => Some(wait_measuring(&mut child)?),
+    };
+    match completed {
+        Some((status, peak_rss)) => {
+            // Processes which outlive the child (such as those it ran in
+            // the background) may hold its pipes open, so the pipes must
+            // also close before the timeout.
+            let (stdout, stdout_closed) = drain_before(stdout, deadline);
+            let (stderr, stderr_closed) = drain_before(stderr, deadline);
+            let mut captured = Captured::new(pid, status, stdout, stderr);
+            captured.peak_rss = peak_rss;
+            if stdout_closed && stderr_closed {
+                Ok(Waited::Completed(captured))
+            } else {
+                Ok(Waited::TimedOut(captured))
+            }
+        }
+        None => {
+            let status = terminate(&mut child, config)?;
+            Ok(Waited::TimedOut(Captured::new(
+                pid,
+                status,
+                stdout.map(Drain::finish_within).unwrap_or_default(),
+                stderr.map(Drain::finish_within).unwrap_or_default(),
+            )))
+        }
+    }
+}
+
+/// Reads `drain` (if any) to completion, or until `deadline` (if set),
+/// returning whether it was read to completion.
+fn drain_before(drain: Option<Drain>, deadline: Option<Instant>) -> (Drained, bool) {
+    match (drain, deadline) {
+        (Some(drain), Some(deadline)) => drain.finish_before(deadline),
+        (Some(drain), None) => (drain.finish(), true),
+        (None, _) => (Drained::default(), true),
+    }
+}
+
+/// Waits up to `timeout` for a child to exit, returning its status if
+/// it did. The child is left running otherwise.
+pub(crate) fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    poll(timeout, || child.try_wait())
+}
+
+/// Calls `f` until it returns a value, or `timeout` elapses.
+fn poll<T, F>(timeout: Duration, mut f: F) -> io::Result<Option<T>>
+where
+    F: FnMut() -> io::Result<Option<T>>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(value) = f()? {
+            return Ok(Some(value));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// Waits for a child to exit, returning its status along with its peak
+/// resident set size in bytes, where that can be measured.
+#[cfg(target_os = "linux")]
+fn wait_measuring(child: &mut Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    use std::os::unix::process::ExitStatusExt;
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    // SAFETY: `rusage` is plain data, for which all zeroes is valid.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: The out-pointers are valid for writes. The child has not
+        // yet been reaped, so `pid` still refers to it.
+        if unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } == pid {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    // `ru_maxrss` is measured in kibibytes.
+    let peak_rss = (usage.ru_maxrss as u64).saturating_mul(1024);
+    Ok((ExitStatus::from_raw(status), Some(peak_rss)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn wait_measuring(child: &mut Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    Ok((child.wait()?, None))
+}
+
+/// As [`wait_timeout`], but measuring the child as [`wait_measuring`].
+#[cfg(target_os = "linux")]
+fn wait_timeout_measuring(
+    child: &mut Child,
+    timeout: Duration,
+) -> io::Result<Option<(ExitStatus, Option<u64>)>> {
+    let pid = child.id() as libc::id_t;
+    let exited = poll(timeout, || {
+        // SAFETY: `siginfo_t` is plain data, for which all zeroes is valid.
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // Checks for an exit without reaping the child, which would discard
+        // its resource usage.
+        let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+        // SAFETY: The out-pointer is valid for writes.
+        if unsafe { libc::waitid(libc::P_PID, pid, &mut info, flags) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `si_pid` is set by `waitid`, and zero if it has not exited.
+        Ok((unsafe { info.si_pid() } != 0).then_some(()))
+    })?;
+    match exited {
+        Some(()) => Ok(Some(wait_measuring(child)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn wait_timeout_measuring(
+    child: &mut Child,
+    timeout: Duration,
+) -> io::Result<Option<(ExitStatus, Option<u64>)>> {
+    Ok(wait_timeout(child, timeout)?.map(|status| (status, None)))
+}
+
+/// Terminates and reaps a child which exceeded its timeout, signalling it
+/// as configured before killing it.
+fn terminate(child: &mut Child, config: &Config) -> io::Result<ExitStatus> {
+    #[cfg(unix)]
+    if let Some((signal, grace)) = config.timeout_kill {
+        if signal_child(child.id(), signal) {
+            if let Some(status) = wait_timeout(child, grace)? {
+                return Ok(status);
+            }
+        }
+    }
+    kill(child)
+}
+
+/// Sends `signal` to the process `pid`, returning true if it was sent.
+#[cfg(unix)]
+pub(crate) fn signal_child(pid: u32, signal: i32) -> bool {
+    // SAFETY: `kill` has no memory-safety requirements.
+    unsafe { libc::kill(pid as libc::pid_t, signal) == 0 }
+}
+
+/// Kills and reaps a child.
+pub(crate) fn kill(child: &mut Child) -> io::Result<ExitStatus> {
+    // The child may have exited since it was last polled; either way,
+    // it is reaped below.
+    let _ = child.kill();
+    child.wait()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads `data`, after first failing with `ErrorKind::Interrupted`.
+    struct Interrupted<'a> {
+        data: &'a [u8],
+        interrupted: bool,
+    }
+
+    impl Read for Interrupted<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            self.data.read(buf)
+        }
+    }
+
+    #[test]
+    fn drain_retries_interrupted_reads() {
+        let pipe = Interrupted {
+            data: b"BAR\n",
+            interrupted: false,
+        };
+        let drained = Drain::new(pipe, None, None).finish();
+        assert_eq!(drained.bytes, b"BAR\n");
+        assert_eq!(drained.len, 4);
+        assert!(!drained.truncated);
+    }
+
+    #[test]
+    fn drain_records_truncation_at_limit() {
+        let drained = Drain::new(&b"BAR\n"[..], None, Some(4)).finish();
+        assert_eq!(drained.bytes, b"BAR\n");
+        assert!(!drained.truncated);
+        let drained = Drain::new(&b"BAR\n"[..], None, Some(3)).finish();
+        assert_eq!((drained.bytes.len(), drained.len), (3, 4));
+        assert!(drained.truncated);
+    }
+}
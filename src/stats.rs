@@ -0,0 +1,42 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Counts of the commands launched by an executor, as accumulated with
+/// [`Executor::with_stats`](crate::Executor::with_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExecStats {
+    /// The number of times commands were launched, counting each retry.
+    pub launches: usize,
+    /// The number of launches which did not succeed, including those
+    /// which were retried.
+    pub failures: usize,
+    /// The number of launches which were killed for exceeding a timeout.
+    pub timeouts: usize,
+    /// The total wall-clock time of every launch.
+    pub total_time: Duration,
+    /// The wall-clock time of the longest launch.
+    pub longest: Duration,
+}
+
+impl ExecStats {
+    /// Records a launch which took `elapsed`.
+    pub(crate) fn record(&mut self, elapsed: Duration, succeeded: bool, timed_out: bool) {
+        self.launches += 1;
+        self.failures += usize::from(!succeeded);
+        self.timeouts += usize::from(timed_out);
+        self.total_time += elapsed;
+        self.longest = self.longest.max(elapsed);
+    }
+}
+
+/// Summarizes the counts, such as for printing at the end of a test.
+impl fmt::Display for ExecStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} launches, {} failed ({} timed out), in {:?} (longest {:?})",
+            self.launches, self.failures, self.timeouts, self.total_time, self.longest
+        )
+    }
+}
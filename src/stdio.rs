@@ -0,0 +1,36 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Where a launched process writes one of its output streams, as
+/// configured by [`Executor::with_stdout`](crate::Executor::with_stdout)
+/// and [`Executor::with_stderr`](crate::Executor::with_stderr).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum StdioMode {
+    /// The stream is captured, and is included in the output and in
+    /// failure diagnostics.
+    #[default]
+    Capture,
+    /// The stream is connected to that of the caller.
+    Inherit,
+    /// The stream is discarded.
+    Null,
+    /// The stream is written to the file at the given path, which is
+    /// created (or truncated) on each launch.
+    File(PathBuf),
+}
+
+/// One of the output streams of a launched process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        })
+    }
+}
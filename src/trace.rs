@@ -0,0 +1,128 @@
+//! Emission of `tracing` spans and events for each execution.
+
+use tracing::{debug, error, field, info_span, Span};
+
+use crate::{command_line, Execution};
+
+/// A span covering a single launch of the command, labelled with the
+/// executor's `context`, whose `pid` and `status` are recorded on
+/// completion.
+pub(crate) fn span(exec: &Execution, context: Option<&str>) -> Span {
+    info_span!(
+        "run",
+        command = %command_line(exec.argv()),
+        context,
+        pid = field::Empty,
+        status = field::Empty,
+    )
+}
+
+/// Records that the command is about to be launched.
+pub(crate) fn launching(exec: &Execution) {
+    debug!(command = %command_line(exec.argv()), "launching command");
+}
+
+/// Records that the command could not be launched.
+pub(crate) fn spawn_failed(exec: &Execution, err: &dyn std::error::Error) {
+    error!(command = %command_line(exec.argv()), error = %err, "failed to launch command");
+}
+
+/// Records the result of the completed command in its `span`, along
+/// with an event describing the failure, if it failed.
+pub(crate) fn completed(span: &Span, exec: &Execution) {
+    let output = exec.output();
+    let status = match exec.timed_out {
+        Some(timeout) => format!("timed out after {:?}", timeout),
+        None => output.status.to_string(),
+    };
+    if let Some(pid) = exec.pid {
+        span.record("pid", pid);
+    }
+    span.record("status", field::display(&status));
+    if !exec.succeeded() {
+        error!(
+            command = %command_line(exec.argv()),
+            pid = exec.pid,
+            status = %status,
+            stdout = %String::from_utf8_lossy(&output.stdout),
+            stderr = %String::from_utf8_lossy(&output.stderr),
+            "command failed"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Executor;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Level, Metadata, Subscriber};
+
+    /// Records the level and fields of each event, and the fields
+    /// recorded on spans.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        records: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct Fields<'a>(&'a mut String);
+
+    impl field::Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            let mut record = String::from("span:");
+            values.record(&mut Fields(&mut record));
+            self.records.lock().unwrap().push(record);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut record = format!("{}:", event.metadata().level());
+            event.record(&mut Fields(&mut record));
+            self.records.lock().unwrap().push(record);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn trace_failed_execution() {
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let executor = Executor::new(vec![("FOO", "BAR")]);
+            executor
+                .try_run(vec!["/bin/bash", "-c", "echo $FOO; exit 1"])
+                .unwrap_err();
+        });
+        let records = recorder.records.lock().unwrap();
+        let pid = records[1].strip_prefix("span: pid=").unwrap();
+        assert!(pid.parse::<u32>().is_ok(), "{}", pid);
+        assert_eq!(
+            *records,
+            vec![
+                format!("{}: message=launching command command=/bin/bash -c 'echo $FOO; exit 1'", Level::DEBUG),
+                format!("span: pid={}", pid),
+                "span: status=exit status: 1".to_string(),
+                format!("{}: message=command failed command=/bin/bash -c 'echo $FOO; exit 1' pid={} status=exit status: 1 stdout=BAR\n stderr=", Level::ERROR, pid),
+            ]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! Transcripts of every launch of a command, as JSON lines.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+
+use crate::{termination_signal, Execution, ExecutionError};
+
+/// A file to which a record of each launch is appended.
+pub(crate) struct Transcript {
+    file: Mutex<File>,
+}
+
+impl Transcript {
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Transcript {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends a record of the launch of `exec`, in the working directory
+    /// `dir`, which completed with `result`. The values of variables are
+    /// redacted unless `show_values` is set.
+    ///
+    /// Each record is written with a single write, so that records of
+    /// concurrent launches are not interleaved, and a crashing test
+    /// leaves every record before it. Failures to write are ignored,
+    /// rather than failing the command.
+    pub(crate) fn record(
+        &self,
+        exec: &Execution,
+        dir: Option<&PathBuf>,
+        result: &Result<(), ExecutionError>,
+        show_values: bool,
+    ) {
+        let lossy = |s: &OsString| s.to_string_lossy().into_owned();
+        let value = |s: &OsString| if show_values { lossy(s) } else { "***".into() };
+        let mut record = json!({
+            "command": exec.argv().iter().map(lossy).collect::<Vec<_>>(),
+            "env": exec
+                .launched_env
+                .iter()
+                .map(|(k, v)| [lossy(k), value(v)])
+                .collect::<Vec<_>>(),
+            "cwd": dir.map(|dir| dir.to_string_lossy().into_owned()),
+            "attempt": exec.attempts,
+            "elapsed_ms": exec.elapsed.map(|elapsed| elapsed.as_millis() as u64),
+        });
+        let fields = record.as_object_mut().unwrap();
+        match (result, &exec.result) {
+            (Ok(()), Some(output)) => {
+                fields.insert("code".into(), json!(output.status.code()));
+                fields.insert("signal".into(), json!(termination_signal(&output.status)));
+                fields.insert("timed_out".into(), Value::Bool(exec.timed_out.is_some()));
+                fields.insert(
+                    "stdout".into(),
+                    String::from_utf8_lossy(&output.stdout).into(),
+                );
+                fields.insert(
+                    "stderr".into(),
+                    String::from_utf8_lossy(&output.stderr).into(),
+                );
+            }
+            (Err(err), _) => {
+                fields.insert("error".into(), err.styled(false).to_string().into());
+            }
+            (Ok(()), None) => (),
+        }
+        let mut line = record.to_string();
+        line.push('\n');
+        let _ = self.file.lock().unwrap().write_all(line.as_bytes());
+    }
+}